@@ -0,0 +1,420 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+// A small ABI encoder, which is used to encode the constructor arguments of a contract. The
+// encoding is described here:
+// https://docs.soliditylang.org/en/v0.8.0/abi-spec.html#formal-specification-of-the-encoding
+
+use ethereum_types::U256;
+use serde::Deserialize;
+use serde_json::Value;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AbiType {
+    Uint(usize),
+    Int(usize),
+    Address,
+    Bool,
+    FixedBytes(usize),
+    Bytes,
+    String,
+    Array(Box<AbiType>),
+    FixedArray(Box<AbiType>, usize),
+    Tuple(Vec<AbiType>),
+}
+
+/// A parameter of a function/constructor in the JSON ABI definition.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AbiParam {
+    #[serde(default)]
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(default)]
+    pub components: Vec<AbiParam>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct AbiEntry {
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    inputs: Vec<AbiParam>,
+}
+
+/// Returns the inputs of the constructor from the JSON ABI definition. Contracts without an
+/// explicit constructor do not have a constructor entry and take no arguments.
+pub fn constructor_inputs(abi: &str) -> anyhow::Result<Vec<AbiParam>> {
+    let entries: Vec<AbiEntry> =
+        serde_json::from_str(abi).map_err(|e| anyhow!("failed to parse ABI definition: {}", e))?;
+    Ok(entries
+        .into_iter()
+        .find(|e| e.kind == "constructor")
+        .map(|e| e.inputs)
+        .unwrap_or_default())
+}
+
+impl AbiType {
+    pub fn from_param(param: &AbiParam) -> anyhow::Result<AbiType> {
+        Self::parse(&param.kind, &param.components)
+    }
+
+    fn parse(kind: &str, components: &[AbiParam]) -> anyhow::Result<AbiType> {
+        // array suffixes are applied from the right, i.e., `uint8[2][]` is a dynamic array of
+        // `uint8[2]`
+        if let Some(stripped) = kind.strip_suffix(']') {
+            let open = stripped
+                .rfind('[')
+                .ok_or_else(|| anyhow!("invalid ABI type {:?}", kind))?;
+            let inner = Box::new(Self::parse(&stripped[..open], components)?);
+            let size = &stripped[open + 1..];
+            return if size.is_empty() {
+                Ok(AbiType::Array(inner))
+            } else {
+                let size = size
+                    .parse()
+                    .map_err(|_| anyhow!("invalid array size in ABI type {:?}", kind))?;
+                Ok(AbiType::FixedArray(inner, size))
+            };
+        }
+
+        let bits = |prefix: &str| -> anyhow::Result<usize> {
+            let bits = &kind[prefix.len()..];
+            if bits.is_empty() {
+                return Ok(256);
+            }
+            match bits.parse() {
+                Ok(b) if b > 0 && b <= 256 && b % 8 == 0 => Ok(b),
+                _ => Err(anyhow!("invalid bit width in ABI type {:?}", kind)),
+            }
+        };
+
+        Ok(match kind {
+            "address" => AbiType::Address,
+            "bool" => AbiType::Bool,
+            "bytes" => AbiType::Bytes,
+            "string" => AbiType::String,
+            "tuple" => AbiType::Tuple(
+                components
+                    .iter()
+                    .map(AbiType::from_param)
+                    .collect::<anyhow::Result<_>>()?,
+            ),
+            _ if kind.starts_with("uint") => AbiType::Uint(bits("uint")?),
+            _ if kind.starts_with("int") => AbiType::Int(bits("int")?),
+            _ if kind.starts_with("bytes") => match kind["bytes".len()..].parse() {
+                Ok(n) if n > 0 && n <= 32 => AbiType::FixedBytes(n),
+                _ => bail!("invalid ABI type {:?}", kind),
+            },
+            _ => bail!("unsupported ABI type {:?}", kind),
+        })
+    }
+
+    fn is_dynamic(&self) -> bool {
+        match self {
+            AbiType::Bytes | AbiType::String | AbiType::Array(_) => true,
+            AbiType::FixedArray(t, _) => t.is_dynamic(),
+            AbiType::Tuple(ts) => ts.iter().any(|t| t.is_dynamic()),
+            _ => false,
+        }
+    }
+}
+
+fn word(v: U256) -> [u8; 32] {
+    let mut w = [0u8; 32];
+    v.to_big_endian(&mut w);
+    w
+}
+
+fn parse_hex_bytes(s: &str) -> anyhow::Result<Vec<u8>> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if !s.is_ascii() || !s.len().is_multiple_of(2) {
+        bail!("invalid hex string {:?}", s);
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&s[i..i + 2], 16).map_err(|_| anyhow!("invalid hex string {:?}", s))
+        })
+        .collect()
+}
+
+/// Parses a non-negative integer given either as JSON number, decimal string or `0x` prefixed hex
+/// string.
+pub fn parse_uint(s: &str) -> anyhow::Result<U256> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        U256::from_str_radix(hex, 16).map_err(|_| anyhow!("invalid hex integer {:?}", s))
+    } else {
+        U256::from_dec_str(s).map_err(|_| anyhow!("invalid integer {:?}", s))
+    }
+}
+
+fn value_as_str(value: &Value) -> anyhow::Result<String> {
+    match value {
+        Value::String(s) => Ok(s.trim().to_string()),
+        Value::Number(n) => Ok(n.to_string()),
+        _ => bail!("expected number or string, got {}", value),
+    }
+}
+
+fn encode_static(t: &AbiType, value: &Value) -> anyhow::Result<Vec<u8>> {
+    Ok(match t {
+        AbiType::Uint(bits) => {
+            let v = parse_uint(&value_as_str(value)?)?;
+            if v.bits() > *bits {
+                bail!("value {} does not fit into uint{}", value, bits);
+            }
+            word(v).to_vec()
+        }
+        AbiType::Int(bits) => {
+            let s = value_as_str(value)?;
+            let (negative, magnitude) = match s.strip_prefix('-') {
+                Some(m) => (true, parse_uint(m)?),
+                None => (false, parse_uint(&s)?),
+            };
+            let limit = U256::one() << (bits - 1);
+            if (negative && magnitude > limit) || (!negative && magnitude >= limit) {
+                bail!("value {} does not fit into int{}", value, bits);
+            }
+            // two's complement
+            let v = if negative {
+                (!magnitude).overflowing_add(U256::one()).0
+            } else {
+                magnitude
+            };
+            word(v).to_vec()
+        }
+        AbiType::Address => {
+            let s = value_as_str(value)?;
+            let bytes = parse_hex_bytes(&s)?;
+            if bytes.len() != 20 {
+                bail!("invalid address {:?}", s);
+            }
+            word(U256::from_big_endian(&bytes)).to_vec()
+        }
+        AbiType::Bool => {
+            let b = match value {
+                Value::Bool(b) => *b,
+                Value::String(s) if s == "true" => true,
+                Value::String(s) if s == "false" => false,
+                _ => bail!("expected bool, got {}", value),
+            };
+            word(if b { U256::one() } else { U256::zero() }).to_vec()
+        }
+        AbiType::FixedBytes(n) => {
+            let bytes = parse_hex_bytes(&value_as_str(value)?)?;
+            if bytes.len() > *n {
+                bail!("value {} does not fit into bytes{}", value, n);
+            }
+            let mut w = [0u8; 32];
+            w[..bytes.len()].copy_from_slice(&bytes);
+            w.to_vec()
+        }
+        _ => unreachable!("{:?} is not a static type", t),
+    })
+}
+
+fn expect_array<'a>(value: &'a Value, t: &AbiType) -> anyhow::Result<&'a Vec<Value>> {
+    value
+        .as_array()
+        .ok_or_else(|| anyhow!("expected JSON array for {:?}, got {}", t, value))
+}
+
+fn encode_value(t: &AbiType, value: &Value) -> anyhow::Result<Vec<u8>> {
+    match t {
+        AbiType::Bytes | AbiType::String => {
+            let bytes = if *t == AbiType::Bytes {
+                parse_hex_bytes(&value_as_str(value)?)?
+            } else {
+                value
+                    .as_str()
+                    .ok_or_else(|| anyhow!("expected string, got {}", value))?
+                    .as_bytes()
+                    .to_vec()
+            };
+            let mut r = word(U256::from(bytes.len())).to_vec();
+            r.extend_from_slice(&bytes);
+            // pad to a multiple of 32 bytes
+            r.resize(r.len() + (32 - bytes.len() % 32) % 32, 0);
+            Ok(r)
+        }
+        AbiType::Array(inner) => {
+            let values = expect_array(value, t)?;
+            let types = vec![(**inner).clone(); values.len()];
+            let mut r = word(U256::from(values.len())).to_vec();
+            r.extend(encode_tuple(&types, values)?);
+            Ok(r)
+        }
+        AbiType::FixedArray(inner, size) => {
+            let values = expect_array(value, t)?;
+            if values.len() != *size {
+                bail!(
+                    "expected {} elements for {:?}, got {}",
+                    size,
+                    t,
+                    values.len()
+                );
+            }
+            let types = vec![(**inner).clone(); *size];
+            encode_tuple(&types, values)
+        }
+        AbiType::Tuple(types) => {
+            let values = expect_array(value, t)?;
+            encode_tuple(types, values)
+        }
+        _ => encode_static(t, value),
+    }
+}
+
+/// Encodes the values as a tuple of the given types, i.e., static values are encoded in place and
+/// dynamic values are appended and referenced by their offset.
+pub fn encode_tuple(types: &[AbiType], values: &[Value]) -> anyhow::Result<Vec<u8>> {
+    if types.len() != values.len() {
+        bail!("expected {} values, got {}", types.len(), values.len());
+    }
+    let encoded: Vec<Vec<u8>> = types
+        .iter()
+        .zip(values.iter())
+        .map(|(t, v)| encode_value(t, v))
+        .collect::<anyhow::Result<_>>()?;
+    let head_size: usize = types
+        .iter()
+        .zip(encoded.iter())
+        .map(|(t, e)| if t.is_dynamic() { 32 } else { e.len() })
+        .sum();
+
+    let mut head = Vec::with_capacity(head_size);
+    let mut tail = Vec::new();
+    for (t, e) in types.iter().zip(encoded) {
+        if t.is_dynamic() {
+            head.extend_from_slice(&word(U256::from(head_size + tail.len())));
+            tail.extend(e);
+        } else {
+            head.extend(e);
+        }
+    }
+    head.extend(tail);
+    Ok(head)
+}
+
+/// ABI encodes the constructor arguments given as JSON array according to the constructor entry
+/// of the ABI definition.
+pub fn encode_constructor_args(abi: &str, args_json: &str) -> anyhow::Result<Vec<u8>> {
+    let inputs = constructor_inputs(abi)?;
+    let types: Vec<AbiType> = inputs
+        .iter()
+        .map(AbiType::from_param)
+        .collect::<anyhow::Result<_>>()?;
+    let values: Vec<Value> = serde_json::from_str(args_json)
+        .map_err(|e| anyhow!("constructor arguments must be a JSON array: {}", e))?;
+    encode_tuple(&types, &values).map_err(|e| {
+        let signature: Vec<String> = inputs
+            .iter()
+            .map(|p| format!("{} {}", p.kind, p.name).trim_end().to_string())
+            .collect();
+        anyhow!(
+            "failed to encode constructor arguments for constructor({}): {}",
+            signature.join(", "),
+            e
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hex(bytes: &[u8]) -> String {
+        bytes.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+
+    const ABI: &str = r#"[
+        {"type": "function", "name": "f", "inputs": [{"name": "x", "type": "bool"}]},
+        {"type": "constructor", "stateMutability": "nonpayable", "inputs": [
+            {"name": "a", "type": "uint256"},
+            {"name": "b", "type": "uint32[]"},
+            {"name": "c", "type": "bytes10"},
+            {"name": "d", "type": "bytes"}
+        ]}
+    ]"#;
+
+    #[test]
+    fn spec_example() {
+        // example from the solidity ABI specification: f(uint,uint32[],bytes10,bytes) with
+        // (0x123, [0x456, 0x789], "1234567890", "Hello, world!")
+        let encoded = encode_constructor_args(
+            ABI,
+            r#"["0x123", [1110, "0x789"], "0x31323334353637383930", "0x48656c6c6f2c20776f726c6421"]"#,
+        )
+        .unwrap();
+        let expected = concat!(
+            "0000000000000000000000000000000000000000000000000000000000000123",
+            "0000000000000000000000000000000000000000000000000000000000000080",
+            "3132333435363738393000000000000000000000000000000000000000000000",
+            "00000000000000000000000000000000000000000000000000000000000000e0",
+            "0000000000000000000000000000000000000000000000000000000000000002",
+            "0000000000000000000000000000000000000000000000000000000000000456",
+            "0000000000000000000000000000000000000000000000000000000000000789",
+            "000000000000000000000000000000000000000000000000000000000000000d",
+            "48656c6c6f2c20776f726c642100000000000000000000000000000000000000",
+        );
+        assert_eq!(hex(&encoded), expected);
+    }
+
+    #[test]
+    fn static_values() {
+        let types = vec![
+            AbiType::Int(8),
+            AbiType::Address,
+            AbiType::Bool,
+            AbiType::FixedArray(Box::new(AbiType::Uint(8)), 2),
+        ];
+        let values: Vec<Value> = serde_json::from_str(
+            r#"[-1, "0x00000000000000000000000000000000000000aa", true, [1, "2"]]"#,
+        )
+        .unwrap();
+        let encoded = encode_tuple(&types, &values).unwrap();
+        assert_eq!(encoded.len(), 5 * 32);
+        assert!(encoded[..32].iter().all(|&b| b == 0xff));
+        assert_eq!(encoded[63], 0xaa);
+        assert_eq!(encoded[95], 1);
+        assert_eq!(encoded[127], 1);
+        assert_eq!(encoded[159], 2);
+
+        let overflow: Vec<Value> = serde_json::from_str("[128]").unwrap();
+        assert!(encode_tuple(&[AbiType::Int(8)], &overflow).is_err());
+        let overflow: Vec<Value> = serde_json::from_str("[256]").unwrap();
+        assert!(encode_tuple(&[AbiType::Uint(8)], &overflow).is_err());
+    }
+
+    #[test]
+    fn parse_types() {
+        let param: AbiParam = serde_json::from_str(
+            r#"{"type": "tuple[2][]", "components": [{"type": "string"}, {"type": "int"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            AbiType::from_param(&param).unwrap(),
+            AbiType::Array(Box::new(AbiType::FixedArray(
+                Box::new(AbiType::Tuple(vec![AbiType::String, AbiType::Int(256)])),
+                2
+            )))
+        );
+        assert!(AbiType::parse("uint7", &[]).is_err());
+        assert!(AbiType::parse("bytes33", &[]).is_err());
+        assert_eq!(constructor_inputs("[]").unwrap().len(), 0);
+    }
+}
@@ -0,0 +1,146 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::Context;
+use ethereum_types::U256;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// An account with code from a state dump.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Account {
+    /// hex encoded runtime code
+    pub code: String,
+    /// storage slots and their values
+    pub storage: BTreeMap<U256, U256>,
+}
+
+/// Parses a storage key or value, which might be given with or without `0x` prefix and leading
+/// zeros.
+fn parse_word(s: &str) -> anyhow::Result<U256> {
+    let hex = s.strip_prefix("0x").unwrap_or(s);
+    if hex.is_empty() {
+        return Ok(U256::zero());
+    }
+    U256::from_str_radix(hex, 16).map_err(|_| anyhow!("invalid storage word {:?}", s))
+}
+
+/// Normalizes an address to 40 lowercase hex characters without `0x` prefix.
+pub fn normalize_address(address: &str) -> anyhow::Result<String> {
+    let hex = address.strip_prefix("0x").unwrap_or(address);
+    if hex.len() != 40 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("invalid address {:?}", address);
+    }
+    Ok(hex.to_ascii_lowercase())
+}
+
+/// Parses the accounts of a state dump. Supported are the `alloc` of a geth genesis file (or only
+/// the alloc object itself), `geth dump` and the state dumped by anvil (`--dump-state`), which all
+/// map addresses to accounts with `code` and `storage`. Accounts without code are skipped.
+pub fn parse_alloc(dump: &Value) -> anyhow::Result<BTreeMap<String, Account>> {
+    let accounts = dump
+        .get("accounts")
+        .or_else(|| dump.get("alloc"))
+        .unwrap_or(dump)
+        .as_object()
+        .ok_or_else(|| anyhow!("expected a JSON object mapping addresses to accounts"))?;
+
+    let mut result = BTreeMap::new();
+    for (address, account) in accounts.iter() {
+        let code = account
+            .get("code")
+            .and_then(|c| c.as_str())
+            .unwrap_or_default()
+            .trim();
+        let code = code.strip_prefix("0x").unwrap_or(code);
+        if code.is_empty() {
+            continue;
+        }
+        let address = normalize_address(address)?;
+
+        let mut storage = BTreeMap::new();
+        if let Some(slots) = account.get("storage").and_then(|s| s.as_object()) {
+            for (key, value) in slots.iter() {
+                let value = value.as_str().ok_or_else(|| {
+                    anyhow!("invalid storage value {} of account {}", value, address)
+                })?;
+                let key = parse_word(key).with_context(|| format!("account {}", address))?;
+                let value = parse_word(value).with_context(|| format!("account {}", address))?;
+                if !value.is_zero() {
+                    storage.insert(key, value);
+                }
+            }
+        }
+
+        result.insert(
+            address,
+            Account {
+                code: code.to_string(),
+                storage,
+            },
+        );
+    }
+    Ok(result)
+}
+
+/// Reads the accounts with code from a state dump file.
+pub fn read_alloc_file(path: &str) -> anyhow::Result<BTreeMap<String, Account>> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read state dump from: {}", path))?;
+    let dump: Value =
+        serde_json::from_str(&s).with_context(|| format!("Failed to parse JSON file {}", path))?;
+    parse_alloc(&dump).with_context(|| format!("invalid state dump {}", path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_dumps() {
+        let alloc = serde_json::json!({
+            "0x00000000000000000000000000000000000000AA": {
+                "balance": "0x0",
+                "code": "0x6001",
+                "storage": {
+                    "0x0": "0x2a",
+                    "0x0000000000000000000000000000000000000000000000000000000000000001": "0x0000000000000000000000000000000000000000000000000000000000000000"
+                }
+            },
+            "00000000000000000000000000000000000000bb": { "balance": "0x1" }
+        });
+        let expected = Account {
+            code: "6001".to_string(),
+            storage: std::iter::once((U256::zero(), U256::from(42))).collect(),
+        };
+
+        let accounts = parse_alloc(&alloc).unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(
+            accounts["00000000000000000000000000000000000000aa"],
+            expected
+        );
+
+        // anvil and geth dump nest the accounts
+        let anvil = serde_json::json!({ "block": {}, "accounts": alloc.clone() });
+        assert_eq!(parse_alloc(&anvil).unwrap(), accounts);
+        let genesis = serde_json::json!({ "config": {}, "alloc": alloc });
+        assert_eq!(parse_alloc(&genesis).unwrap(), accounts);
+
+        let invalid = serde_json::json!({ "0x1234": { "code": "0x00" } });
+        assert!(parse_alloc(&invalid).is_err());
+    }
+}
@@ -863,6 +863,48 @@ impl Program {
         }
     }
 
+    /// Parses the init code (i.e., the constructor) of a contract. The init code is followed by
+    /// the runtime code, which is copied to memory and returned by the constructor, and the ABI
+    /// encoded constructor arguments are appended during deployment. So we only parse the init code
+    /// up to the start of the embedded runtime code. The embedded runtime code still contains the
+    /// zero placeholders of immutable variables, so the bytes in `immutable_ranges` of the
+    /// runtime code are ignored when looking for it. Returns `None` if the runtime code is not
+    /// part of the init code.
+    ///
+    /// In contrast to the runtime code, the CODESIZE of the init code depends on the constructor
+    /// arguments, so CODESIZE is never treated as a constant here.
+    pub fn new_init_code(
+        init_code: &[u8],
+        runtime_code: &[u8],
+        immutable_ranges: &[std::ops::Range<usize>],
+    ) -> Option<Program> {
+        let code_end = if runtime_code.is_empty() {
+            init_code.len()
+        } else {
+            let mut masked: BitVec = BitVec::repeat(false, runtime_code.len());
+            for range in immutable_ranges.iter() {
+                let range = range.start.min(runtime_code.len())..range.end.min(runtime_code.len());
+                masked[range].fill(true);
+            }
+            init_code.windows(runtime_code.len()).position(|w| {
+                w.iter()
+                    .zip(runtime_code.iter())
+                    .enumerate()
+                    .all(|(i, (a, b))| a == b || masked[i])
+            })?
+        };
+
+        let mut program = Program::new(&init_code[..code_end]);
+        for bb in program.basic_blocks.iter_mut() {
+            for inst in bb.instructions.iter_mut() {
+                if inst.opcode == Ok(Instruction::CODESIZE) {
+                    inst.value = None;
+                }
+            }
+        }
+        Some(program)
+    }
+
     pub fn optimize(&mut self) {
         for bb in self.basic_blocks.iter_mut() {
             bb.optimize();
@@ -883,6 +925,52 @@ mod tests {
         assert!(U256_ZERO.is_zero());
     }
 
+    #[test]
+    fn init_code_stops_at_runtime_code() {
+        /*
+         * 0: CODESIZE [38];
+         * 1: PUSH1 0x4 [60 04];
+         * 3: STOP [00];
+         * 4: runtime code: PUSH1 0x0 [60 00]; STOP [00];
+         * 7: constructor arguments
+         */
+        let init_code = hexutil::read_hex("0x386004006000002a2a").unwrap();
+        let runtime_code = hexutil::read_hex("0x600000").unwrap();
+        let program = Program::new_init_code(&init_code, &runtime_code, &[]).unwrap();
+        let instructions: Vec<&IInstruction> = program
+            .basic_blocks
+            .iter()
+            .flat_map(|bb| bb.instructions.iter())
+            .collect();
+        assert_eq!(instructions.len(), 3);
+        assert_eq!(instructions[0].opcode, Ok(Instruction::CODESIZE));
+        assert_eq!(instructions[0].value, None);
+        assert_eq!(program.meta.len(), 4);
+
+        // the runtime code is not part of the init code
+        assert!(Program::new_init_code(&init_code, &[0xff], &[]).is_none());
+    }
+
+    #[test]
+    fn init_code_with_immutables() {
+        /*
+         * 0: PUSH1 0x5 [60 05];
+         * 2: PUSH1 0x1 [60 01];
+         * 4: STOP [00];
+         * 5: runtime code: PUSH2 <immutable> [61 00 00]; STOP [00];
+         * 9: constructor arguments
+         */
+        let init_code = hexutil::read_hex("0x600560010061000000000000002a").unwrap();
+        // the runtime code with the value of the immutable filled in
+        let runtime_code = hexutil::read_hex("0x61beef00").unwrap();
+        assert!(Program::new_init_code(&init_code, &runtime_code, &[]).is_none());
+        let program = Program::new_init_code(&init_code, &runtime_code, &[1..2, 2..3]).unwrap();
+        assert_eq!(program.meta.len(), 5);
+        // the other bytes of the runtime code must still match
+        let runtime_code = hexutil::read_hex("0x62beef00").unwrap();
+        assert!(Program::new_init_code(&init_code, &runtime_code, &[1..2, 2..3]).is_none());
+    }
+
     #[test]
     fn build_program() {
         /*
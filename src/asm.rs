@@ -0,0 +1,375 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+// A minimal assembler and disassembler for EVM bytecode. The assembly format has one instruction
+// per line:
+//
+//     ; comments start with ';' or '//'
+//     loop:                   ; label definition (does not emit a JUMPDEST by itself)
+//         JUMPDEST
+//         PUSH1 0x2a          ; hex or decimal immediate
+//         PUSH 1000           ; PUSH without size uses the smallest PUSHn for the value
+//         PUSH2 @loop         ; reference to a label (PUSH @loop uses PUSH2)
+//         JUMP
+//         .data 0xfe01        ; raw bytes, e.g., for undefined opcodes or metadata
+//
+// The disassembly listing uses the same format, such that it can be assembled again to the
+// identical bytecode.
+
+use crate::instructions::Instruction;
+use ethereum_types::U256;
+use std::collections::BTreeMap;
+
+/// Size of label references in `PUSH @label` without an explicit size.
+const LABEL_PUSH_BYTES: usize = 2;
+
+/// A decoded instruction of the disassembly.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DecodedInstruction {
+    pub pc: usize,
+    /// the instruction or the undefined opcode
+    pub opcode: Result<Instruction, u8>,
+    /// immediate bytes of PUSH instructions (truncated at the end of the code)
+    pub immediate: Vec<u8>,
+}
+
+impl DecodedInstruction {
+    /// Whether the PUSH immediate is cut off by the end of the bytecode.
+    pub fn is_truncated(&self) -> bool {
+        match self.opcode {
+            Ok(inst) => inst.push_bytes().unwrap_or(0) != self.immediate.len(),
+            Err(_) => false,
+        }
+    }
+
+    /// Number of bytes of the instruction in the bytecode.
+    pub fn size(&self) -> usize {
+        1 + self.immediate.len()
+    }
+
+    /// Mnemonic of the instruction.
+    pub fn mnemonic(&self) -> String {
+        mnemonic(self.opcode)
+    }
+}
+
+/// Mnemonic of the instruction; undefined opcodes are named after their byte value.
+pub fn mnemonic(opcode: Result<Instruction, u8>) -> String {
+    match opcode {
+        Ok(inst) => inst.info().name.to_string(),
+        Err(b) => format!("INVALID_{:#04x}", b),
+    }
+}
+
+/// Decodes the bytecode into instructions, in the same way `Program::new` does.
+pub fn decode(bytecode: &[u8]) -> Vec<DecodedInstruction> {
+    let mut instructions = vec![];
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        let opcode = Instruction::from_u8(bytecode[pc]).ok_or(bytecode[pc]);
+        let push_bytes = opcode.ok().and_then(|i| i.push_bytes()).unwrap_or(0);
+        let end = (pc + 1 + push_bytes).min(bytecode.len());
+        let inst = DecodedInstruction {
+            pc,
+            opcode,
+            immediate: bytecode[pc + 1..end].to_vec(),
+        };
+        pc += inst.size();
+        instructions.push(inst);
+    }
+    instructions
+}
+
+fn to_hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn label_name(pc: usize) -> String {
+    format!("pc_{:x}", pc)
+}
+
+/// Disassembles the bytecode to a listing, which can be assembled again with `assemble`. Every
+/// JUMPDEST gets a label and PUSH immediates that refer to a JUMPDEST are emitted as label
+/// references.
+pub fn disassemble(bytecode: &[u8]) -> String {
+    let instructions = decode(bytecode);
+    let jumpdests: std::collections::BTreeSet<usize> = instructions
+        .iter()
+        .filter(|i| i.opcode == Ok(Instruction::JUMPDEST))
+        .map(|i| i.pc)
+        .collect();
+
+    let mut listing = String::new();
+    for inst in instructions.iter() {
+        if jumpdests.contains(&inst.pc) {
+            listing += &format!("{}:\n", label_name(inst.pc));
+        }
+        let line = if inst.is_truncated() || inst.opcode.is_err() {
+            // not a valid instruction, so we keep the raw bytes
+            let mut bytes = vec![bytecode[inst.pc]];
+            bytes.extend_from_slice(&inst.immediate);
+            format!(".data 0x{}", to_hex_string(&bytes))
+        } else if inst.immediate.is_empty() {
+            inst.mnemonic()
+        } else {
+            let target = if inst.immediate.len() <= 8 {
+                Some(U256::from_big_endian(&inst.immediate).low_u64() as usize)
+            } else {
+                None
+            };
+            match target.filter(|t| jumpdests.contains(t)) {
+                Some(target) => format!("{} @{}", inst.mnemonic(), label_name(target)),
+                None => format!("{} 0x{}", inst.mnemonic(), to_hex_string(&inst.immediate)),
+            }
+        };
+        listing += &format!("    {:<48} ; {:#06x}\n", line, inst.pc);
+    }
+    listing
+}
+
+/// Looks up an instruction by its mnemonic (case insensitive). `KECCAK256` is accepted as alias
+/// of `SHA3`.
+fn instruction_by_mnemonic(mnemonic: &str) -> Option<Instruction> {
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let mnemonic = if mnemonic == "KECCAK256" {
+        "SHA3".to_string()
+    } else {
+        mnemonic
+    };
+    (0..=255u8)
+        .filter_map(Instruction::from_u8)
+        .find(|i| i.info().name == mnemonic)
+}
+
+fn parse_number(s: &str) -> Option<U256> {
+    if let Some(hex) = s.strip_prefix("0x") {
+        U256::from_str_radix(hex, 16).ok()
+    } else {
+        U256::from_dec_str(s).ok()
+    }
+}
+
+fn parse_hex_bytes(s: &str) -> Option<Vec<u8>> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if !s.is_ascii() || !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&s[i..i + 2], 16).ok())
+        .collect()
+}
+
+/// Minimal number of bytes to represent the value (at least one).
+fn byte_len(value: &U256) -> usize {
+    std::cmp::max(1, value.bits().div_ceil(8))
+}
+
+/// An item of the assembled code; label references are resolved after all labels are known.
+enum Item {
+    Bytes(Vec<u8>),
+    LabelRef {
+        line: usize,
+        label: String,
+        size: usize,
+    },
+}
+
+/// Assembles the assembly text to bytecode.
+pub fn assemble(source: &str) -> anyhow::Result<Vec<u8>> {
+    let mut items = vec![];
+    let mut labels: BTreeMap<String, usize> = BTreeMap::new();
+    let mut pc = 0;
+
+    for (idx, line) in source.lines().enumerate() {
+        let lineno = idx + 1;
+        let line = line.split(';').next().unwrap();
+        let line = line.split("//").next().unwrap().trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(label) = line.strip_suffix(':') {
+            let label = label.trim();
+            if label.is_empty() || label.contains(char::is_whitespace) {
+                bail!("line {}: invalid label {:?}", lineno, label);
+            }
+            if labels.insert(label.to_string(), pc).is_some() {
+                bail!("line {}: label {} is defined twice", lineno, label);
+            }
+            continue;
+        }
+
+        let mut parts = line.split_whitespace();
+        let mnemonic = parts.next().unwrap();
+        let operand = parts.next();
+        if let Some(extra) = parts.next() {
+            bail!("line {}: unexpected {:?} after instruction", lineno, extra);
+        }
+
+        if mnemonic == ".data" {
+            let bytes = operand
+                .and_then(parse_hex_bytes)
+                .ok_or_else(|| anyhow!("line {}: .data requires hex bytes", lineno))?;
+            pc += bytes.len();
+            items.push(Item::Bytes(bytes));
+            continue;
+        }
+
+        let (inst, size) = if mnemonic.eq_ignore_ascii_case("PUSH") {
+            let operand =
+                operand.ok_or_else(|| anyhow!("line {}: PUSH requires an operand", lineno))?;
+            let size = if operand.starts_with('@') {
+                LABEL_PUSH_BYTES
+            } else {
+                let value = parse_number(operand)
+                    .ok_or_else(|| anyhow!("line {}: invalid number {:?}", lineno, operand))?;
+                byte_len(&value)
+            };
+            let inst = Instruction::from_u8(Instruction::PUSH1 as u8 + size as u8 - 1).unwrap();
+            (inst, size)
+        } else {
+            let inst = instruction_by_mnemonic(mnemonic)
+                .ok_or_else(|| anyhow!("line {}: unknown instruction {:?}", lineno, mnemonic))?;
+            (inst, inst.push_bytes().unwrap_or(0))
+        };
+
+        items.push(Item::Bytes(vec![inst as u8]));
+        pc += 1;
+        if size == 0 {
+            if operand.is_some() {
+                bail!("line {}: {} does not take an operand", lineno, mnemonic);
+            }
+            continue;
+        }
+
+        let operand =
+            operand.ok_or_else(|| anyhow!("line {}: {} requires an operand", lineno, mnemonic))?;
+        if let Some(label) = operand.strip_prefix('@') {
+            items.push(Item::LabelRef {
+                line: lineno,
+                label: label.to_string(),
+                size,
+            });
+        } else {
+            let value = parse_number(operand)
+                .ok_or_else(|| anyhow!("line {}: invalid number {:?}", lineno, operand))?;
+            if byte_len(&value) > size {
+                bail!(
+                    "line {}: value {} does not fit into {}",
+                    lineno,
+                    operand,
+                    mnemonic
+                );
+            }
+            let mut word = [0u8; 32];
+            value.to_big_endian(&mut word);
+            items.push(Item::Bytes(word[32 - size..].to_vec()));
+        }
+        pc += size;
+    }
+
+    let mut bytecode = Vec::with_capacity(pc);
+    for item in items.into_iter() {
+        match item {
+            Item::Bytes(bytes) => bytecode.extend(bytes),
+            Item::LabelRef { line, label, size } => {
+                let target = *labels
+                    .get(&label)
+                    .ok_or_else(|| anyhow!("line {}: undefined label {}", line, label))?;
+                let value = U256::from(target);
+                if byte_len(&value) > size {
+                    bail!(
+                        "line {}: address of label {} does not fit into {} bytes",
+                        line,
+                        label,
+                        size
+                    );
+                }
+                let mut word = [0u8; 32];
+                value.to_big_endian(&mut word);
+                bytecode.extend_from_slice(&word[32 - size..]);
+            }
+        }
+    }
+    Ok(bytecode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOOP: &str = "
+        ; count down from 10
+        PUSH1 10
+    loop:
+        JUMPDEST
+        PUSH1 1
+        SWAP1
+        SUB
+        DUP1
+        PUSH @loop      // jump back while not zero
+        JUMPI
+        STOP
+    ";
+
+    #[test]
+    fn assemble_loop() {
+        let bytecode = assemble(LOOP).unwrap();
+        assert_eq!(
+            bytecode,
+            hexutil::read_hex("0x600a5b60019003806100025700").unwrap()
+        );
+        assert_eq!(
+            assemble("PUSH 0x1000\nsha3\nkeccak256").unwrap(),
+            vec![0x61, 0x10, 0x00, 0x20, 0x20]
+        );
+    }
+
+    #[test]
+    fn assemble_errors() {
+        let err = |s: &str| assemble(s).unwrap_err().to_string();
+        assert!(err("FOO").contains("line 1: unknown instruction"));
+        assert!(err("PUSH1 0x100").contains("does not fit"));
+        assert!(err("PUSH2 @nowhere").contains("undefined label nowhere"));
+        assert!(err("a:\na:").contains("defined twice"));
+        assert!(err("ADD 1").contains("does not take an operand"));
+        assert!(err("PUSH1").contains("requires an operand"));
+    }
+
+    #[test]
+    fn example_contract() {
+        let bytecode = assemble(include_str!("../contracts/countdown.evm")).unwrap();
+        assert_eq!(&bytecode[..5], &[0x60, 0x0a, 0x60, 0x00, 0x5b]);
+        assert_eq!(assemble(&disassemble(&bytecode)).unwrap(), bytecode);
+    }
+
+    #[test]
+    fn disassemble_round_trip() {
+        let bytecode = assemble(LOOP).unwrap();
+        let listing = disassemble(&bytecode);
+        assert!(listing.contains("pc_2:\n"), "{}", listing);
+        assert!(listing.contains("PUSH2 @pc_2"), "{}", listing);
+        assert_eq!(assemble(&listing).unwrap(), bytecode);
+
+        // undefined opcodes and a truncated PUSH at the end
+        let bytecode = hexutil::read_hex("0x600c0cfe6101").unwrap();
+        let listing = disassemble(&bytecode);
+        assert!(listing.contains(".data 0x0c"), "{}", listing);
+        assert!(listing.contains(".data 0x6101"), "{}", listing);
+        assert_eq!(assemble(&listing).unwrap(), bytecode);
+    }
+}
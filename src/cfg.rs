@@ -0,0 +1,216 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::analysis::{BasicBlock, Operand, Program};
+use crate::asm::mnemonic;
+use crate::disasm::metadata_range;
+use crate::instructions::Instruction;
+use serde_json::{json, Value};
+use std::convert::TryFrom;
+
+/// Kind of an edge in the control flow graph.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeKind {
+    /// JUMP or the taken branch of a JUMPI
+    Jump,
+    /// the not-taken branch of a JUMPI or a block ending without a control flow instruction
+    FallThrough,
+}
+
+impl EdgeKind {
+    fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Jump => "jump",
+            EdgeKind::FallThrough => "fallthrough",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub from: usize,
+    pub to: usize,
+    pub kind: EdgeKind,
+}
+
+/// The control flow graph of the runtime code as far as it can be recovered statically, i.e.,
+/// jumps to targets that are constant within the basic block. All other jumps are recorded as
+/// dynamic jumps.
+#[derive(Clone, Debug, Default)]
+pub struct Cfg {
+    /// start addresses of the basic blocks (excluding the metadata)
+    pub blocks: Vec<usize>,
+    pub edges: Vec<Edge>,
+    /// basic blocks ending with a JUMP/JUMPI whose target is only known at runtime
+    pub dynamic_jumps: Vec<usize>,
+    /// basic blocks ending with a JUMP/JUMPI to a constant target that is not a JUMPDEST
+    pub invalid_jumps: Vec<usize>,
+}
+
+/// Returns the constant jump target of a JUMP/JUMPI, which is only available after constant
+/// folding (`Program::optimize`).
+fn jump_target(bb: &BasicBlock) -> Option<usize> {
+    let inst = bb.instructions.last()?;
+    match inst.operands.as_ref()?.first()? {
+        Operand::Constant((_, t)) if t.bits() <= 64 => usize::try_from(t.low_u64()).ok(),
+        _ => None,
+    }
+}
+
+/// Builds the control flow graph of an (optimized) program.
+pub fn build_cfg(program: &Program) -> Cfg {
+    let code_end = metadata_range(&program.bytecode).map_or(program.bytecode.len(), |r| r.start);
+    let blocks: Vec<&BasicBlock> = program
+        .basic_blocks
+        .iter()
+        .filter(|bb| bb.address < code_end)
+        .collect();
+
+    let mut cfg = Cfg {
+        blocks: blocks.iter().map(|bb| bb.address).collect(),
+        ..Default::default()
+    };
+    for (idx, bb) in blocks.iter().enumerate() {
+        let last = match bb.instructions.last() {
+            Some(inst) => inst.opcode,
+            None => continue,
+        };
+        if let Ok(Instruction::JUMP) | Ok(Instruction::JUMPI) = last {
+            match jump_target(bb) {
+                Some(t) if program.meta.is_valid_jumpdest(t) => cfg.edges.push(Edge {
+                    from: bb.address,
+                    to: t,
+                    kind: EdgeKind::Jump,
+                }),
+                Some(_) => cfg.invalid_jumps.push(bb.address),
+                None => cfg.dynamic_jumps.push(bb.address),
+            }
+        }
+        let falls_through = match last {
+            Ok(i) => !i.stops() && i != Instruction::JUMP,
+            Err(_) => false,
+        };
+        if falls_through && !bb.ends_on_invalid {
+            if let Some(next) = blocks.get(idx + 1) {
+                cfg.edges.push(Edge {
+                    from: bb.address,
+                    to: next.address,
+                    kind: EdgeKind::FallThrough,
+                });
+            }
+        }
+    }
+    cfg
+}
+
+/// Renders the control flow graph in the Graphviz dot format. Blocks with dynamic jumps are drawn
+/// with a dashed border, blocks with invalid jumps in red.
+pub fn cfg_dot(program: &Program, cfg: &Cfg, name: &str) -> String {
+    let mut dot = format!(
+        "digraph \"{}\" {{\n    node [shape=box fontname=monospace];\n",
+        name
+    );
+    for bb in program
+        .basic_blocks
+        .iter()
+        .filter(|bb| cfg.blocks.contains(&bb.address))
+    {
+        let last = bb
+            .instructions
+            .last()
+            .map(|i| mnemonic(i.opcode))
+            .unwrap_or_default();
+        let mut style = String::new();
+        if cfg.dynamic_jumps.contains(&bb.address) {
+            style += " style=dashed";
+        }
+        if cfg.invalid_jumps.contains(&bb.address) {
+            style += " color=red";
+        }
+        dot += &format!(
+            "    b{0:x} [label=\"{0:#06x}\\n{1} instructions\\n{2}\"{3}];\n",
+            bb.address,
+            bb.instructions.len(),
+            last,
+            style
+        );
+    }
+    for edge in cfg.edges.iter() {
+        let style = match edge.kind {
+            EdgeKind::Jump => "",
+            EdgeKind::FallThrough => " [style=dotted]",
+        };
+        dot += &format!("    b{:x} -> b{:x}{};\n", edge.from, edge.to, style);
+    }
+    dot += "}\n";
+    dot
+}
+
+/// Renders the control flow graph as JSON.
+pub fn cfg_json(cfg: &Cfg) -> Value {
+    json!({
+        "blocks": cfg.blocks,
+        "edges": cfg.edges.iter().map(|e| json!({
+            "from": e.from,
+            "to": e.to,
+            "kind": e.kind.name(),
+        })).collect::<Vec<_>>(),
+        "dynamic_jumps": cfg.dynamic_jumps,
+        "invalid_jumps": cfg.invalid_jumps,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn control_flow_edges() {
+        // 0x00: CALLDATASIZE PUSH1 0x0b JUMPI
+        // 0x04: PUSH1 0x00 CALLDATALOAD JUMP
+        // 0x08: PUSH1 0x02 JUMP (no JUMPDEST at 0x02)
+        // 0x0b: JUMPDEST STOP
+        let bytecode = hexutil::read_hex("0x36600b57600035566002565b00").unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+        let cfg = build_cfg(&program);
+
+        assert_eq!(cfg.blocks, vec![0x0, 0x4, 0x8, 0xb]);
+        assert_eq!(
+            cfg.edges,
+            vec![
+                Edge {
+                    from: 0x0,
+                    to: 0xb,
+                    kind: EdgeKind::Jump
+                },
+                Edge {
+                    from: 0x0,
+                    to: 0x4,
+                    kind: EdgeKind::FallThrough
+                },
+            ]
+        );
+        assert_eq!(cfg.dynamic_jumps, vec![0x4]);
+        assert_eq!(cfg.invalid_jumps, vec![0x8]);
+
+        let dot = cfg_dot(&program, &cfg, "test");
+        assert!(dot.contains("b0 -> bb;\n"), "{}", dot);
+        assert!(dot.contains("b0 -> b4 [style=dotted];\n"), "{}", dot);
+        assert!(dot.contains("b4 [label=\"0x0004\\n3 instructions\\nJUMP\" style=dashed]"));
+        assert_eq!(cfg_json(&cfg)["edges"][1]["kind"], "fallthrough");
+    }
+}
@@ -18,13 +18,103 @@ use ethereum_types::U256;
 use std::collections::{BTreeMap, BTreeSet};
 use std::convert::TryFrom;
 use std::fs::File;
+use std::io::Write;
+use std::path::Path;
 
 use crate::analysis::*;
+use crate::asm::disassemble;
+use crate::disasm::metadata_range;
 use crate::instructions::*;
-use crate::sourcemap::SourceMap;
+use crate::output::{BuildFragment, OutputLayout};
+use crate::sourcemap::{
+    instruction_entry, instruction_location, SourceLocation, SourceMap, SourceMapEntry,
+};
 
 use anyhow::Context;
 
+/// Controls which comparisons are reported to the fuzzer via the comparison tracing macros.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum CompareTracing {
+    /// Only trace LT/GT/SLT/SGT/EQ when both operands are computed at runtime. Comparisons with
+    /// a constant operand are skipped, since the constant already ends up in the dictionary.
+    #[default]
+    Dictionary,
+    /// Trace all comparison-like instructions, i.e., also comparisons with constant operands,
+    /// ISZERO and SUB/XOR whose result is tested with ISZERO. Every trace site gets a unique ID
+    /// (the pc of the instruction), which allows CmpLog-style input-to-state replacement.
+    CmpLog,
+}
+
+impl std::str::FromStr for CompareTracing {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "dict" => Ok(CompareTracing::Dictionary),
+            "cmplog" => Ok(CompareTracing::CmpLog),
+            _ => Err(anyhow!("unknown comparison tracing mode {:?}", s)),
+        }
+    }
+}
+
+/// Options that influence the generated code.
+#[derive(Clone, Debug, Default)]
+pub struct CodegenOptions {
+    pub compare_tracing: CompareTracing,
+    /// emit the `TRACE_SLOAD`/`TRACE_SSTORE`/`TRACE_MEM` hooks for storage and memory accesses
+    pub trace_state: bool,
+    /// emit the `before_external_call`/`after_external_call` hooks around calls and creates
+    pub trace_calls: bool,
+    /// emit `#line` directives pointing to the solidity source (requires a sourcemap)
+    pub line_directives: bool,
+    /// also translate the init code and emit a `construct()` method (requires the constructor
+    /// bytecode)
+    pub translate_constructor: bool,
+    /// write a disassembly listing of the runtime code, which can be assembled again, to
+    /// `contracts/<name>.evm`
+    pub emit_asm: bool,
+    /// write a build-system fragment listing the generated files of the contract
+    pub build_fragment: Option<BuildFragment>,
+    pub run_clang_format: bool,
+}
+
+/// clang-format executables to try, whatever is installed really
+const CLANG_FORMATS_TRY: [&str; 5] = [
+    "clang-format",
+    "clang-format-13",
+    "clang-format-12",
+    "clang-format-11",
+    "clang-format-10",
+];
+
+/// Formats the given files in place with the first clang-format that can be launched and waits
+/// for it to finish. A failing clang-format is an error, a missing one only a warning, which is
+/// returned to the caller.
+fn run_clang_format(files: &[&Path]) -> anyhow::Result<Option<String>> {
+    for proc in CLANG_FORMATS_TRY.iter() {
+        match std::process::Command::new(proc)
+            .arg("--sort-includes")
+            .arg("-i")
+            .args(files)
+            .output()
+        {
+            Ok(out) if out.status.success() => return Ok(None),
+            Ok(out) => bail!(
+                "{} failed on {:?} ({}): {}",
+                proc,
+                files,
+                out.status,
+                String::from_utf8_lossy(&out.stderr).trim()
+            ),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e).with_context(|| format!("failed to run {}", proc)),
+        }
+    }
+    Ok(Some(
+        "WARNING: Could not launch clang-format(-1[0-3])".to_string(),
+    ))
+}
+
 fn emit_header() -> &'static str {
     "// auto-generated by evm2cpp
     "
@@ -99,6 +189,66 @@ _evm_start:
     ret
 }
 
+/// Collects the constants of a program, i.e., the pushed values and the operands known after
+/// constant folding. This is roughly the set of constants interned during codegen, but without the
+/// bytes of the metadata that happen to decode to PUSH instructions.
+pub fn program_constants(program: &Program) -> BTreeSet<U256> {
+    let code_end = metadata_range(&program.bytecode).map_or(program.bytecode.len(), |r| r.start);
+    let mut constants = BTreeSet::new();
+    for inst in program
+        .basic_blocks
+        .iter()
+        .flat_map(|bb| bb.instructions.iter())
+        .filter(|inst| inst.address < code_end)
+    {
+        if inst.opcode.is_ok_and(|i| i.is_push()) {
+            constants.extend(inst.value.iter().flatten().cloned());
+        }
+        for operand in inst.operands.iter().flatten() {
+            if let Operand::Constant((_, v)) = operand {
+                constants.insert(*v);
+            }
+        }
+    }
+    constants
+}
+
+/// Returns the entries of the fuzzing dictionary (AFL/libFuzzer format) for the given constants.
+/// Zero, valid jump destinations and values consisting only of 0x00, 0x01 and 0xff bytes (bitmasks,
+/// -1 for various bit widths, ...) are not interesting and skipped.
+pub fn dictionary_entries<'a>(
+    program: &Program,
+    constants: impl IntoIterator<Item = &'a U256>,
+) -> Vec<String> {
+    let mut entries = vec![];
+    for const_val in constants.into_iter() {
+        if const_val.is_zero()
+            || if const_val.bits() <= 64 {
+                program
+                    .meta
+                    .is_valid_jumpdest(usize::try_from(const_val.as_u64()).unwrap())
+            } else {
+                false
+            }
+        {
+            continue;
+        }
+
+        let mut bytes = [0u8; 32];
+        const_val.to_big_endian(&mut bytes);
+
+        // check if the bytes are actually interesting
+        if bytes.iter().any(|&b| b != 0 && b != 1 && b != 0xff) {
+            let mut s = String::new();
+            for b in bytes.iter().skip_while(|&&b| b == 0) {
+                s.push_str(&format!("\\x{:02X}", b));
+            }
+            entries.push(format!("\"{}\"\n", s));
+        }
+    }
+    entries
+}
+
 fn format_constant(constant: U256, interned_globals: &mut BTreeMap<U256, String>) -> String {
     //if constant.bits() < 64 || constant < U256::from(std::u64::MAX) {
     //    format!("({:#x})n", constant)
@@ -159,11 +309,393 @@ fn emit_jump(inst: &IInstruction, codemeta: &CodeMeta) -> String {
     }
 }
 
+/// Placeholder line, which is replaced with a `#line` directive that switches back to the
+/// generated C++ file, once the final line numbers are known.
+const LINE_RESET_MARKER: &str = "#line __EVM2CPP_LINE_RESET__";
+
+/// Formats the source code of a sourcemap entry as a (disabled) comment block, such that the
+/// source can contain any character sequence.
+fn format_source_annotation(entry: Option<&SourceMapEntry>) -> String {
+    match entry.and_then(|e| e.location.as_ref().map(|l| (e, l))) {
+        Some((entry, location)) => {
+            let source: String = entry.source.chars().take(200).collect();
+            let source = source.replace("*/", "*\\/");
+            let kind = if location.generated {
+                "Compiler-generated Source"
+            } else {
+                "Source"
+            };
+            format!(
+                "#if 0\n/****** {} {} ******\n{}\n**************/\n#endif\n",
+                kind, location, source
+            )
+        }
+        None => "/* no source */\n".to_string(),
+    }
+}
+
+fn format_line_directive(line_number: usize, file: &str) -> String {
+    format!(
+        "#line {} \"{}\"\n",
+        line_number,
+        file.replace('\\', "\\\\").replace('"', "\\\"")
+    )
+}
+
+/// Replaces all `LINE_RESET_MARKER` lines in `code` with `#line` directives that point back to
+/// the generated file. `first_line` is the line number of the first line of `code` in `file`.
+fn resolve_line_resets(code: &str, first_line: usize, file: &str) -> String {
+    let mut r = String::with_capacity(code.len());
+    for (i, line) in code.split_inclusive('\n').enumerate() {
+        if line.trim_end() == LINE_RESET_MARKER {
+            // the directive sets the line number of the following line
+            r += &format_line_directive(first_line + i + 1, file);
+        } else {
+            r += line;
+        }
+    }
+    r
+}
+
+/// Recomputes the line numbers of the `#line` directives that point back to `file` after the
+/// generated code was reformatted (i.e., by clang-format), which shifts the lines.
+fn renumber_line_resets(code: &str, file: &str) -> String {
+    let directive = format_line_directive(0, file);
+    let suffix = directive.trim_start_matches("#line 0").trim_end();
+    let mut r = String::with_capacity(code.len());
+    for (i, line) in code.split_inclusive('\n').enumerate() {
+        let trimmed = line.trim();
+        if trimmed.starts_with("#line ") && trimmed.ends_with(suffix) {
+            r += &format_line_directive(i + 2, file);
+        } else {
+            r += line;
+        }
+    }
+    r
+}
+
+/// Wraps a writer and keeps track of the number of written lines. Required to emit `#line`
+/// directives referring to the generated file itself.
+struct LineCountingWriter<W: Write> {
+    inner: W,
+    lines: usize,
+}
+
+impl<W: Write> LineCountingWriter<W> {
+    fn new(inner: W) -> Self {
+        LineCountingWriter { inner, lines: 0 }
+    }
+}
+
+impl<W: Write> Write for LineCountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.lines += buf[..n].iter().filter(|&&c| c == b'\n').count();
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn is_comparison(op: Instruction) -> bool {
+    matches!(
+        op,
+        Instruction::SGT | Instruction::GT | Instruction::SLT | Instruction::LT | Instruction::EQ
+    )
+}
+
+/// Returns the indices of all SUB/XOR instructions in the basic block, whose result is tested
+/// with ISZERO in the same basic block. The solidity compiler (and hand-written code) sometimes
+/// implements equality checks using this pattern instead of EQ.
+fn sub_xor_iszero_sites(bb: &BasicBlock) -> BTreeSet<usize> {
+    let mut sites = BTreeSet::new();
+    for inst in bb.instructions.iter() {
+        if inst.ignoreable || inst.opcode != Ok(Instruction::ISZERO) {
+            continue;
+        }
+        if let Some(Operand::InstructionRef((other_idx, _))) =
+            inst.operands.as_ref().and_then(|o| o.first())
+        {
+            let other = &bb.instructions[*other_idx];
+            if matches!(other.opcode, Ok(Instruction::SUB) | Ok(Instruction::XOR)) {
+                sites.insert(*other_idx);
+            }
+        }
+    }
+    sites
+}
+
+/// Decides whether the instruction at `idx` in the basic block is reported to the comparison
+/// tracing.
+fn is_traced_comparison(
+    bb: &BasicBlock,
+    idx: usize,
+    op: Instruction,
+    mode: CompareTracing,
+    sub_xor_sites: &BTreeSet<usize>,
+) -> bool {
+    let operands = if let Some(operands) = &bb.instructions[idx].operands {
+        operands
+    } else {
+        return false;
+    };
+    match (mode, op) {
+        // if one of the two is a constant, then it is added to the dictionary, so we do not
+        // need to emit compare tracing code.
+        (CompareTracing::Dictionary, op) if is_comparison(op) => {
+            !operands.iter().any(|o| matches!(o, Operand::Constant(_)))
+        }
+        (CompareTracing::CmpLog, op) if is_comparison(op) => true,
+        (CompareTracing::CmpLog, Instruction::SUB) | (CompareTracing::CmpLog, Instruction::XOR) => {
+            sub_xor_sites.contains(&idx)
+        }
+        (CompareTracing::CmpLog, Instruction::ISZERO) => match operands[0] {
+            // negating the result of a comparison or testing an already traced SUB/XOR does not
+            // give the fuzzer any new information.
+            Operand::InstructionRef((other_idx, _)) => {
+                let other = &bb.instructions[other_idx];
+                !(sub_xor_sites.contains(&other_idx)
+                    || matches!(other.opcode, Ok(o) if is_comparison(o) || o == Instruction::ISZERO))
+            }
+            _ => true,
+        },
+        _ => false,
+    }
+}
+
+/// Pops all `Operand::StackPop` operands of the instruction into local variables, such that the
+/// generated operand expressions can be used multiple times (e.g., by the tracing code and the
+/// opcode handler) without popping the EVM stack twice.
+fn materialize_stack_pops(idx: usize, inst: &IInstruction, operand_gen: &mut [String]) -> String {
+    let mut r = String::new();
+    if let Some(operands) = &inst.operands {
+        for (o_idx, o) in operands.iter().enumerate() {
+            let v = format!("p_{}_{}", idx, o_idx);
+            // might have been materialized already by another tracing hook
+            if matches!(o, Operand::StackPop(_)) && operand_gen[o_idx] != v {
+                r += &format!("const uint256_t {} = ctxt->s.pop();\n", v);
+                operand_gen[o_idx] = v;
+            }
+        }
+    }
+    r
+}
+
+/// Fallback for eEVM versions without per-site comparison tracing. `TRACE_COMP` only handles the
+/// comparison opcodes, so the ISZERO/SUB/XOR sites are not forwarded to it.
+const CMPLOG_FALLBACK: &str = "
+// fallback for eEVM versions without per-site comparison tracing
+#ifndef TRACE_CMPLOG
+#define TRACE_CMPLOG(site, op, a, b) \\
+    do { \\
+        if ((op) == eevm::Opcode::LT || (op) == eevm::Opcode::GT || \\
+            (op) == eevm::Opcode::SLT || (op) == eevm::Opcode::SGT || \\
+            (op) == eevm::Opcode::EQ) { \\
+            TRACE_COMP(op, a, b); \\
+        } \\
+    } while (0)
+#endif
+
+";
+
+fn emit_compare_trace(
+    inst: &IInstruction,
+    op: Instruction,
+    operand_gen: &[String],
+    mode: CompareTracing,
+    interned_globals: &mut BTreeMap<U256, String>,
+) -> String {
+    let mut inst_name = inst.get_name();
+    inst_name.make_ascii_uppercase();
+    let args = if op == Instruction::ISZERO {
+        // ISZERO is traced as a comparison with zero
+        format!(
+            "{}, {}",
+            operand_gen[0],
+            format_constant(U256::zero(), interned_globals)
+        )
+    } else {
+        operand_gen.join(", ")
+    };
+    match mode {
+        CompareTracing::Dictionary => {
+            format!("TRACE_COMP(eevm::Opcode::{}, {});\n", inst_name, args)
+        }
+        CompareTracing::CmpLog => format!(
+            "TRACE_CMPLOG({:#x}, eevm::Opcode::{}, {});\n",
+            inst.address, inst_name, args
+        ),
+    }
+}
+
+/// Length of a memory region accessed by an instruction.
+enum MemoryLength {
+    /// length is given by the operand with the index
+    Operand(usize),
+    /// instruction always accesses the given number of bytes
+    Fixed(usize),
+}
+
+/// Returns the memory regions accessed by the instruction as pairs of the operand index of the
+/// memory offset and the length of the region.
+fn memory_accesses(op: Instruction) -> Vec<(usize, MemoryLength)> {
+    use MemoryLength::*;
+    match op {
+        Instruction::MLOAD | Instruction::MSTORE => vec![(0, Fixed(32))],
+        Instruction::MSTORE8 => vec![(0, Fixed(1))],
+        Instruction::SHA3 | Instruction::RETURN | Instruction::REVERT => vec![(0, Operand(1))],
+        Instruction::LOG0
+        | Instruction::LOG1
+        | Instruction::LOG2
+        | Instruction::LOG3
+        | Instruction::LOG4 => vec![(0, Operand(1))],
+        Instruction::CALLDATACOPY | Instruction::CODECOPY | Instruction::RETURNDATACOPY => {
+            vec![(0, Operand(2))]
+        }
+        Instruction::EXTCODECOPY => vec![(1, Operand(3))],
+        Instruction::CREATE | Instruction::CREATE2 => vec![(1, Operand(2))],
+        Instruction::CALL | Instruction::CALLCODE => vec![(3, Operand(4)), (5, Operand(6))],
+        Instruction::DELEGATECALL | Instruction::STATICCALL => {
+            vec![(2, Operand(3)), (4, Operand(5))]
+        }
+        _ => vec![],
+    }
+}
+
+fn is_traced_state_access(op: Instruction) -> bool {
+    op == Instruction::SLOAD || op == Instruction::SSTORE || !memory_accesses(op).is_empty()
+}
+
+/// Emits the storage and memory access tracing hooks for the given instruction. The hooks are
+/// macros, which are compiled out unless the eEVM harness defines them.
+fn emit_state_trace(
+    inst: &IInstruction,
+    op: Instruction,
+    operand_gen: &[String],
+    interned_globals: &mut BTreeMap<U256, String>,
+) -> String {
+    let mut r = String::new();
+    match op {
+        Instruction::SLOAD => {
+            r += &format!("TRACE_SLOAD({:#x}, {});\n", inst.address, operand_gen[0]);
+        }
+        Instruction::SSTORE => {
+            r += &format!(
+                "TRACE_SSTORE({:#x}, {}, {});\n",
+                inst.address, operand_gen[0], operand_gen[1]
+            );
+        }
+        _ => {}
+    }
+    for (offset_idx, length) in memory_accesses(op) {
+        let length = match length {
+            MemoryLength::Operand(i) => operand_gen[i].clone(),
+            MemoryLength::Fixed(n) => format_constant(U256::from(n), interned_globals),
+        };
+        r += &format!(
+            "TRACE_MEM({:#x}, {}, {});\n",
+            inst.address, operand_gen[offset_idx], length
+        );
+    }
+    r
+}
+
+fn is_external_call(op: Instruction) -> bool {
+    matches!(
+        op,
+        Instruction::CALL
+            | Instruction::STATICCALL
+            | Instruction::DELEGATECALL
+            | Instruction::CALLCODE
+            | Instruction::CREATE
+            | Instruction::CREATE2
+    )
+}
+
+/// Fallback for eEVM versions without the external call hooks.
+const CALL_HOOKS_FALLBACK: &str = "
+// external call hooks; compiled out unless implemented by the eEVM version
+#ifdef EVM2CPP_HAS_EXTERNAL_CALL_HOOKS
+#include <map>
+#else
+#define before_external_call(pc, kind, target, value, storage_writes)
+#define after_external_call(pc, kind, success, created)
+#endif
+
+";
+
+/// Declares the storage write-set of the current frame, i.e., the last value written to every slot
+/// by an SSTORE so far, which is handed to `before_external_call`. Like the hooks, it only exists
+/// if the eEVM version implements them. Writes of other frames are visible to the harness via
+/// `TRACE_SSTORE`.
+const STORAGE_WRITES_DECL: &str = "
+#ifdef EVM2CPP_HAS_EXTERNAL_CALL_HOOKS
+std::map<uint256_t, uint256_t> storage_writes;
+#endif
+";
+
+/// Records an SSTORE in the storage write-set (see `STORAGE_WRITES_DECL`).
+fn emit_storage_write(slot: &str, value: &str) -> String {
+    format!(
+        "#ifdef EVM2CPP_HAS_EXTERNAL_CALL_HOOKS\nstorage_writes[{}] = {};\n#endif\n",
+        slot, value
+    )
+}
+
+/// Emits the `before_external_call(pc, kind, target, value, storage_writes)` hook. For
+/// CREATE/CREATE2 the target is zero, since the address is not known before the call.
+fn emit_before_external_call(
+    inst: &IInstruction,
+    op: Instruction,
+    operand_gen: &[String],
+    interned_globals: &mut BTreeMap<U256, String>,
+) -> String {
+    let zero = format_constant(U256::zero(), interned_globals);
+    let (target, value) = match op {
+        Instruction::CALL | Instruction::CALLCODE => (&operand_gen[1], &operand_gen[2]),
+        Instruction::DELEGATECALL | Instruction::STATICCALL => (&operand_gen[1], &zero),
+        Instruction::CREATE | Instruction::CREATE2 => (&zero, &operand_gen[0]),
+        _ => panic!("{:?} is not an external call", op),
+    };
+    format!(
+        "before_external_call({:#x}, eevm::Opcode::{}, {}, {}, storage_writes);\n",
+        inst.address,
+        op.info().name,
+        target,
+        value
+    )
+}
+
+/// Emits the `after_external_call(pc, kind, success, created)` hook for the call whose result is
+/// `result`. `created` is the address of the new contract for CREATE/CREATE2 (zero if the
+/// creation failed) and zero for calls.
+fn emit_after_external_call(
+    inst: &IInstruction,
+    op: Instruction,
+    result: &str,
+    interned_globals: &mut BTreeMap<U256, String>,
+) -> String {
+    let created = match op {
+        Instruction::CREATE | Instruction::CREATE2 => result.to_string(),
+        _ => format_constant(U256::zero(), interned_globals),
+    };
+    format!(
+        "after_external_call({:#x}, eevm::Opcode::{}, {} != 0, {});\n",
+        inst.address,
+        op.info().name,
+        result,
+        created
+    )
+}
+
 fn emit_basic_block(
     bb: &BasicBlock,
     codemeta: &CodeMeta,
     interned_globals: &mut BTreeMap<U256, String>,
     sourcemap: Option<&SourceMap>,
+    options: &CodegenOptions,
 ) -> String {
     let mut r = format_label(bb.address);
     r += ":\n{ /* <============ */ \n";
@@ -178,27 +710,26 @@ fn emit_basic_block(
 
     //r += &format!("/* {:?} */\n\n", bb);
 
-    r += &format!("on_bb_start({});\n", bb.address);
-
     if let Some(sm) = sourcemap {
-        if bb.instructions.len() > 0 {
-            let inst = &bb.instructions[0];
-            let line_ref = if inst.global_idx >= sm.len() {
-                &sm[sm.len() - 1].line
-            } else {
-                &sm[inst.global_idx].line
-            };
-            let line_sanit = line_ref[0..std::cmp::min(line_ref.len(), 200)].to_string();
-            let line_sanit = line_sanit.replace("*/", "*\\/");
-            r += &format!(
-                "#if 0\n/****** Source ******\n{}\n**************/\n#endif\n\n",
-                line_sanit
-            );
+        let mut locations = bb
+            .instructions
+            .iter()
+            .filter_map(|inst| instruction_location(sm, inst))
+            .peekable();
+        if locations.peek().is_some() && locations.all(|l| l.generated) {
+            r += "/* compiler-generated code */\n";
         }
     }
 
+    r += &format!("on_bb_start({});\n", bb.address);
+
     let mut instructions_gen = Vec::<String>::with_capacity(bb.instructions.len());
     let mut stack_peeks = BTreeMap::<usize, BTreeSet<usize>>::new();
+    let cmp_sub_xor_sites = if options.compare_tracing == CompareTracing::CmpLog {
+        sub_xor_iszero_sites(bb)
+    } else {
+        BTreeSet::new()
+    };
 
     let mut finalizer_emitted = false;
     let mut finalizer: String = "/* BB finalizer */\n".to_string();
@@ -262,8 +793,28 @@ fn emit_basic_block(
         finalizer += "/* no BB returns */\n";
     }
 
+    let mut last_location: Option<Option<&SourceLocation>> = None;
+    let mut last_line_directive: Option<(&str, usize)> = None;
     for (idx, inst) in bb.instructions.iter().enumerate() {
         let mut r = String::new();
+        if let Some(sm) = sourcemap {
+            // annotate every group of consecutive instructions that map to the same source range
+            let location = instruction_location(sm, inst);
+            if last_location != Some(location) {
+                r += &format_source_annotation(instruction_entry(sm, inst));
+                last_location = Some(location);
+            }
+            if options.line_directives {
+                // generated sources do not exist on disk, so we keep pointing to the last line
+                if let Some(loc) = location.filter(|l| !l.generated) {
+                    let line = (loc.file.as_str(), loc.start_line);
+                    if last_line_directive != Some(line) {
+                        r += &format_line_directive(line.1, line.0);
+                        last_line_directive = Some(line);
+                    }
+                }
+            }
+        }
         match inst.opcode {
             Ok(op) => {
                 // print out the instructions
@@ -302,40 +853,15 @@ fn emit_basic_block(
                     continue;
                 }
 
-                match op {
-                    Instruction::SGT
-                    | Instruction::GT
-                    | Instruction::SLT
-                    | Instruction::LT
-                    | Instruction::EQ => {
-                        if let Some(operands) = &inst.operands {
-                            let mut inst_name = inst.get_name().clone();
-                            inst_name.make_ascii_uppercase();
-                            let mut trace_code =
-                                format!("TRACE_COMP(eevm::Opcode::{}, ", inst_name);
-                            let mut has_const = false;
-                            for (o_idx, o) in operands.iter().enumerate() {
-                                match *o {
-                                    Operand::Constant(_) => {
-                                        has_const = true;
-                                        break;
-                                    }
-                                    _ => {
-                                        trace_code += &format!("{}, ", operand_gen[o_idx]);
-                                    }
-                                }
-                            }
-
-                            // if one of the two is a constant, then they are added to dictionary,
-                            // so we do not need to emit compare tracing code.
-                            if !has_const {
-                                r += &trace_code[0..trace_code.len() - 2];
-                                r += ");\n";
-                            }
-                        }
-                        // what
-                    }
-                    _ => {}
+                if is_traced_comparison(bb, idx, op, options.compare_tracing, &cmp_sub_xor_sites) {
+                    r += &materialize_stack_pops(idx, inst, &mut operand_gen);
+                    r += &emit_compare_trace(
+                        inst,
+                        op,
+                        &operand_gen,
+                        options.compare_tracing,
+                        interned_globals,
+                    );
                 }
 
                 // else emit code
@@ -399,6 +925,18 @@ fn emit_basic_block(
                         }
                     }
                 } else {
+                    if options.trace_state && is_traced_state_access(op) {
+                        r += &materialize_stack_pops(idx, inst, &mut operand_gen);
+                        r += &emit_state_trace(inst, op, &operand_gen, interned_globals);
+                    }
+                    if options.trace_calls && is_external_call(op) {
+                        r += &materialize_stack_pops(idx, inst, &mut operand_gen);
+                        r += &emit_before_external_call(inst, op, &operand_gen, interned_globals);
+                    }
+                    if options.trace_calls && op == Instruction::SSTORE {
+                        r += &materialize_stack_pops(idx, inst, &mut operand_gen);
+                        r += &emit_storage_write(&operand_gen[0], &operand_gen[1]);
+                    }
                     let ret_count = iinfo.ret;
                     let mut output_vars = Vec::<String>::with_capacity(ret_count);
                     if ret_count == 1 {
@@ -439,6 +977,14 @@ fn emit_basic_block(
                     | Instruction::CALLCODE
                     | Instruction::CREATE
                     | Instruction::CREATE2 => {
+                        if options.trace_calls {
+                            r += &emit_after_external_call(
+                                inst,
+                                op,
+                                &format!("v_{}_0", idx),
+                                interned_globals,
+                            );
+                        }
                         r += "if (ctxt->error) goto exit_label;\n";
                     }
                     _ => {}
@@ -466,55 +1012,49 @@ fn emit_basic_block(
         r += "\n";
     }
 
-    if let Some(sm) = sourcemap {
-        if !bb.instructions.is_empty() {
-            let inst = &bb.instructions[bb.instructions.len() - 1];
-            let line_ref = if inst.global_idx >= sm.len() {
-                &sm[sm.len() - 1].line
-            } else {
-                &sm[inst.global_idx].line
-            };
-            let line_sanit = line_ref[0..std::cmp::min(line_ref.len(), 200)].to_string();
-            let line_sanit = line_sanit.replace("*/", "*\\/");
-            r += &format!(
-                "#if 0\n/****** Source ******\n{}\n**************/\n#endif\n\n",
-                line_sanit
-            );
-        }
-    }
-
     if !finalizer_emitted {
         r += &finalizer;
     }
 
     r += "}";
 
+    if last_line_directive.is_some() {
+        r += "\n";
+        r += LINE_RESET_MARKER;
+        r += "\n";
+    }
+
     r
 }
 
 //fn translate_bb
 
+/// Translates the contract to C++ and returns the warnings of the translation. The caller prints
+/// them, such that contracts translated in parallel are reported in order.
+#[allow(clippy::too_many_arguments)]
 pub fn translate_to_c(
-    eevm_path: &std::path::Path,
+    output: &OutputLayout,
     contract_id: &str,
     bytecode: Vec<u8>,
     constructor_bytecode: Option<Vec<u8>>,
+    immutable_ranges: &[std::ops::Range<usize>],
+    constructor_args: &[u8],
+    initial_storage: &[(U256, U256)],
     sourcemap: Option<SourceMap>,
-    run_clang_format: bool,
-) -> anyhow::Result<()> {
+    constructor_sourcemap: Option<SourceMap>,
+    options: &CodegenOptions,
+) -> anyhow::Result<Vec<String>> {
+    let mut warnings = vec![];
     let mut evm_program = Program::new(&bytecode);
 
     // run the optimizer
     evm_program.optimize();
 
-    let s = format!("contracts/{}.cpp", contract_id);
-    let file_path = eevm_path.join(s);
-    let s = format!("include/eEVM/evm2cpp/contracts/{}.h", contract_id);
-    let h_file_path = eevm_path.join(s);
-    let all_h_file_path = eevm_path.join("include/eEVM/evm2cpp/contracts.h");
-
-    let s = format!("contracts/{}.bb_list", contract_id);
-    let bb_list_file_path = eevm_path.join(s);
+    let file_path = output.contract_source(contract_id);
+    let h_file_path = output.contract_header(contract_id);
+    let bb_list_file_path = output.bb_list(contract_id);
+    let asm_file_path = output.asm(contract_id);
+    let dict_file_path = output.dict(contract_id);
 
     // create a new scope to auto-close file at end
     {
@@ -531,14 +1071,41 @@ pub fn translate_to_c(
             file.write_all(b"\n")?;
         }
 
-        let mut file = File::create(&file_path)
-            .with_context(|| format!("failed to create file at {:?}", file_path))?;
+        if options.emit_asm {
+            std::fs::write(&asm_file_path, disassemble(&bytecode))
+                .with_context(|| format!("failed to create file at {:?}", asm_file_path))?;
+        }
+
+        let mut file = LineCountingWriter::new(
+            File::create(&file_path)
+                .with_context(|| format!("failed to create file at {:?}", file_path))?,
+        );
         let mut h_file = File::create(&h_file_path)
             .with_context(|| format!("failed to create file at {:?}", h_file_path))?;
 
         file.write_all(emit_header().as_bytes())?;
         h_file.write_all(emit_header().as_bytes())?;
 
+        let translate_constructor = options.translate_constructor && constructor_bytecode.is_some();
+        let construct_decl = if translate_constructor {
+            "
+#ifdef EVM2CPP_HAS_CONSTRUCT_METHOD
+      void construct() override;
+#endif
+"
+        } else {
+            ""
+        };
+        let initial_storage_decl = if !initial_storage.is_empty() {
+            "
+#ifdef EVM2CPP_HAS_INITIAL_STORAGE_METHOD
+      const std::vector<std::pair<uint256_t, uint256_t>>& initial_storage() override;
+#endif
+"
+        } else {
+            ""
+        };
+
         h_file.write_all(
             format!(
                 "
@@ -564,7 +1131,7 @@ namespace eevm
       const Code& constructor_bytecode() override;
       const Code& constructor_args() override;
       const std::string& name() override;
-
+{1}{2}
 #ifdef EVM2CPP_HAS_DUPLICATE_METHOD
       std::unique_ptr<SpecializedProcessor> duplicate() override {{
          return std::make_unique<EVM2CPP_{0}>();
@@ -574,7 +1141,7 @@ namespace eevm
 }}
 
 ",
-                contract_id
+                contract_id, construct_decl, initial_storage_decl
             )
             .as_bytes(),
         )?;
@@ -594,6 +1161,33 @@ namespace eevm
             .as_bytes(),
         )?;
 
+        if options.trace_state {
+            file.write_all(
+                "
+// storage and memory access tracing hooks; compiled out unless defined by the harness
+#ifndef TRACE_SLOAD
+#define TRACE_SLOAD(pc, slot)
+#endif
+#ifndef TRACE_SSTORE
+#define TRACE_SSTORE(pc, slot, value)
+#endif
+#ifndef TRACE_MEM
+#define TRACE_MEM(pc, offset, length)
+#endif
+
+"
+                .as_bytes(),
+            )?;
+        }
+
+        if options.compare_tracing == CompareTracing::CmpLog {
+            file.write_all(CMPLOG_FALLBACK.as_bytes())?;
+        }
+
+        if options.trace_calls {
+            file.write_all(CALL_HOOKS_FALLBACK.as_bytes())?;
+        }
+
         file.write_all(
             "
 // this is generated code and we might have emitted some variables/labels that are not actually used anymore
@@ -638,13 +1232,58 @@ void eevm::EVM2CPP_{}::dispatch() {{
     ",
             contract_id
         );
+        if options.trace_calls {
+            dispatch_code += STORAGE_WRITES_DECL;
+        }
         dispatch_code += &emit_jumptable(code_meta);
 
         for bb in evm_program.basic_blocks.iter() {
-            dispatch_code += &emit_basic_block(bb, code_meta, &mut globals, sourcemap.as_ref());
+            dispatch_code +=
+                &emit_basic_block(bb, code_meta, &mut globals, sourcemap.as_ref(), options);
         }
         dispatch_code += "exit_label:\n  return;\n}\n\n";
 
+        // the constructor is translated into a separate function with its own jump table
+        let mut construct_code = match &constructor_bytecode {
+            Some(init_code) if translate_constructor => {
+                let mut init_program =
+                    Program::new_init_code(init_code, &bytecode, immutable_ranges).ok_or_else(
+                        || {
+                            anyhow!(
+                                "cannot translate the constructor of contract {} - its runtime code is not part of the constructor bytecode",
+                                contract_id
+                            )
+                        },
+                    )?;
+                init_program.optimize();
+
+                let mut code = format!(
+                    "
+#ifdef EVM2CPP_HAS_CONSTRUCT_METHOD
+// constructor code
+void eevm::EVM2CPP_{}::construct() {{
+    ",
+                    contract_id
+                );
+                if options.trace_calls {
+                    code += STORAGE_WRITES_DECL;
+                }
+                code += &emit_jumptable(&init_program.meta);
+                for bb in init_program.basic_blocks.iter() {
+                    code += &emit_basic_block(
+                        bb,
+                        &init_program.meta,
+                        &mut globals,
+                        constructor_sourcemap.as_ref(),
+                        options,
+                    );
+                }
+                code += "exit_label:\n  return;\n}\n#endif\n\n";
+                code
+            }
+            _ => String::new(),
+        };
+
         // now we write the interned globals
         let global_vars: Vec<String> = globals
             .iter()
@@ -654,8 +1293,18 @@ void eevm::EVM2CPP_{}::dispatch() {{
             .collect();
         file.write_all(global_vars.join("\n").as_bytes())?;
 
+        if options.line_directives {
+            let file_name = file_path.to_string_lossy();
+            dispatch_code = resolve_line_resets(&dispatch_code, file.lines + 1, &file_name);
+        }
         file.write_all(dispatch_code.as_bytes())?;
 
+        if options.line_directives {
+            let file_name = file_path.to_string_lossy();
+            construct_code = resolve_line_resets(&construct_code, file.lines + 1, &file_name);
+        }
+        file.write_all(construct_code.as_bytes())?;
+
         if let Some(constructor_bytecode) = constructor_bytecode {
             file.write_all(
                 emit_bytecode(&constructor_bytecode, "_constructor_bytecode").as_bytes(),
@@ -677,114 +1326,678 @@ const eevm::Code& eevm::EVM2CPP_{0}::constructor_bytecode() {{
     return _constructor_bytecode;
 }}
 
-// TODO: update this if you want constructor arguments.
-const eevm::Code _constructor_args = {{}};
-
+// ABI encoded constructor arguments
+{1}
 const eevm::Code& eevm::EVM2CPP_{0}::constructor_args() {{
     return _constructor_args;
 }}
 ",
-                contract_id
+                contract_id,
+                emit_bytecode(constructor_args, "_constructor_args")
             )
             .as_bytes(),
         )?;
 
-        use std::fs::OpenOptions;
-        use std::io::prelude::*;
-
-        let mut all_h_file = OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(&all_h_file_path)
-            .with_context(|| {
-                format!("failed to open file at {:?} for appending", all_h_file_path)
-            })?;
-        let contract_include = format!(
-            "#include \"{}\"\n",
-            h_file_path
-                .strip_prefix(eevm_path)
-                .unwrap()
-                .strip_prefix("include/")
-                .unwrap()
-                .to_str()
-                .unwrap()
-        );
-        all_h_file.write_all(contract_include.as_bytes())?;
+        if !initial_storage.is_empty() {
+            let slots: Vec<String> = initial_storage
+                .iter()
+                .map(|(key, value)| format!("    {{{:#x}_u256, {:#x}_u256}},\n", key, value))
+                .collect();
+            file.write_all(
+                format!(
+                    "
+#ifdef EVM2CPP_HAS_INITIAL_STORAGE_METHOD
+// initial storage (slot, value) of the account, e.g., taken from a state dump
+static const std::vector<std::pair<uint256_t, uint256_t>> _initial_storage = {{
+{1}}};
+
+const std::vector<std::pair<uint256_t, uint256_t>>& eevm::EVM2CPP_{0}::initial_storage() {{
+    return _initial_storage;
+}}
+#endif
+",
+                    contract_id,
+                    slots.concat()
+                )
+                .as_bytes(),
+            )?;
+        }
+
+        // register the contract in contracts.h
+        output.update_contracts_header()?;
 
         // finally write a dictionary file for the contract
-        let s = format!("fuzz/dict/{}.dict", contract_id);
-        let dict_file_path = eevm_path.join(s);
         let mut dict_file = File::create(dict_file_path.clone())
             .with_context(|| format!("failed to create dictionary file at {:?}", dict_file_path))?;
-        for (const_val, _) in globals.into_iter() {
-            // filter 0 and some unintersting values, such as bitmasks, -1 (for various bit widths)
-            if const_val.is_zero()
-                || if const_val.bits() <= 64 {
-                    evm_program
-                        .meta
-                        .is_valid_jumpdest(usize::try_from(const_val.as_u64()).unwrap())
-                } else {
-                    false
-                }
-            {
-                continue;
-            }
-
-            let mut bytes = [0u8; 32];
-            const_val.to_big_endian(&mut bytes);
-
-            // check if the bytes are actually interesting
-            if bytes.iter().any(|&b| b != 0 && b != 1 && b != 0xff) {
-                let mut s = String::new();
-                for b in bytes.iter().skip_while(|&&b| b == 0) {
-                    s.push_str(&format!("\\x{:02X}", b));
-                }
-                let entry = format!("\"{}\"\n", s);
-                dict_file.write_all(entry.as_bytes())?;
-            }
+        for entry in dictionary_entries(&evm_program, globals.keys()) {
+            dict_file.write_all(entry.as_bytes())?;
         }
     }
 
-    if run_clang_format {
-        const CLANG_FORMATS_TRY: [&str; 5] = [
-            "clang-format",
-            "clang-format-13",
-            "clang-format-12",
-            "clang-format-11",
-            "clang-format-10",
-        ];
-        // try to launch all kinds of clang-format, whatever is installed really
-        'outter: for proc in &CLANG_FORMATS_TRY {
-            for f in &[&file_path, &h_file_path, &all_h_file_path] {
-                // files are closed, we run clang-format if possible
-                match std::process::Command::new(proc)
-                    .arg("--sort-includes")
-                    .arg("-i")
-                    .arg(&f)
-                    .spawn()
-                {
-                    Ok(_) => {}
-                    Err(e) => {
-                        match e.kind() {
-                            std::io::ErrorKind::NotFound => {
-                                if proc == CLANG_FORMATS_TRY.last().unwrap() {
-                                    println!("WARNING: Could not launch clang-format(-1[0-3])",);
-                                }
-                                continue 'outter;
-                            }
-                            _ => println!(
-                                "WARNING: failed to spawn {} (error: {} [kind={:?}])",
-                                proc,
-                                e,
-                                e.kind()
-                            ),
-                        };
-                    }
-                }
-            }
-            break;
+    if options.run_clang_format {
+        // contracts.h is shared by all contracts and already generated in a sorted form
+        warnings.extend(run_clang_format(&[&file_path, &h_file_path])?);
+        if options.line_directives {
+            let code = std::fs::read_to_string(&file_path)
+                .with_context(|| format!("failed to read {:?}", file_path))?;
+            std::fs::write(
+                &file_path,
+                renumber_line_resets(&code, &file_path.to_string_lossy()),
+            )
+            .with_context(|| format!("failed to write {:?}", file_path))?;
         }
     }
 
-    anyhow::Result::Ok(())
+    let mut files = vec![
+        ("source", file_path.as_path()),
+        ("header", h_file_path.as_path()),
+        ("bb_list", bb_list_file_path.as_path()),
+        ("dict", dict_file_path.as_path()),
+    ];
+    if options.emit_asm {
+        files.push(("asm", asm_file_path.as_path()));
+    }
+    // the ABI is written before the translation (if available)
+    let abi_file_path = output.abi(contract_id);
+    if abi_file_path.exists() {
+        files.push(("abi", abi_file_path.as_path()));
+    }
+    let fragment_path = if let Some(format) = options.build_fragment {
+        Some(output.write_build_fragment(format, contract_id, &files)?)
+    } else {
+        None
+    };
+    if let Some(fragment_path) = fragment_path.as_ref() {
+        files.push(("build_fragment", fragment_path.as_path()));
+    }
+    output.record(contract_id, &files)?;
+
+    anyhow::Result::Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// ```
+    /// 0: PUSH1 0x42
+    /// 2: PUSH1 0x00
+    /// 4: CALLDATALOAD
+    /// 5: SUB
+    /// 6: ISZERO
+    /// 7: PUSH1 0x0b
+    /// 9: JUMPI
+    /// a: STOP
+    /// b: JUMPDEST
+    /// c: STOP
+    /// ```
+    const SUB_ISZERO_BYTECODE: &str = "0x60426000350315600b57005b00";
+
+    fn sub_iszero_program() -> Program {
+        let bytecode = hexutil::read_hex(SUB_ISZERO_BYTECODE).unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+        program
+    }
+
+    #[test]
+    fn cmplog_sub_iszero_site() {
+        let program = sub_iszero_program();
+        let bb = &program.basic_blocks[0];
+        let sites = sub_xor_iszero_sites(bb);
+        assert_eq!(sites.into_iter().collect::<Vec<_>>(), vec![3]);
+
+        let sites = sub_xor_iszero_sites(bb);
+        assert!(is_traced_comparison(
+            bb,
+            3,
+            Instruction::SUB,
+            CompareTracing::CmpLog,
+            &sites
+        ));
+        assert!(!is_traced_comparison(
+            bb,
+            3,
+            Instruction::SUB,
+            CompareTracing::Dictionary,
+            &sites
+        ));
+        // the ISZERO only tests the already traced SUB
+        assert!(!is_traced_comparison(
+            bb,
+            4,
+            Instruction::ISZERO,
+            CompareTracing::CmpLog,
+            &sites
+        ));
+    }
+
+    /// Emits the first basic block of the given bytecode with the given comparison tracing mode.
+    fn compare_tracing_code(bytecode: &str, mode: CompareTracing) -> String {
+        let bytecode = hexutil::read_hex(bytecode).unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+        let options = CodegenOptions {
+            compare_tracing: mode,
+            ..Default::default()
+        };
+        let mut globals = BTreeMap::new();
+        emit_basic_block(
+            &program.basic_blocks[0],
+            &program.meta,
+            &mut globals,
+            None,
+            &options,
+        )
+    }
+
+    /// Returns the (site, opcode) pairs of all emitted `TRACE_CMPLOG` calls.
+    fn cmplog_sites(code: &str) -> Vec<(String, String)> {
+        code.match_indices("TRACE_CMPLOG(")
+            .map(|(i, m)| {
+                let mut args = code[i + m.len()..].split(", ");
+                let site = args.next().unwrap().to_string();
+                let op = args.next().unwrap().to_string();
+                (site, op)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn cmplog_sub_xor_iszero() {
+        let code = compare_tracing_code(SUB_ISZERO_BYTECODE, CompareTracing::CmpLog);
+        // the SUB is traced as comparison of its operands, the ISZERO testing it is not traced
+        assert_eq!(
+            cmplog_sites(&code),
+            vec![("0x5".to_string(), "eevm::Opcode::SUB".to_string())]
+        );
+        assert!(code.contains("TRACE_CMPLOG(0x5, eevm::Opcode::SUB, v_2_0, c_42);"));
+
+        // 0: PUSH1 0x42
+        // 2: PUSH1 0x00
+        // 4: CALLDATALOAD
+        // 5: XOR
+        // 6: ISZERO
+        // 7: PUSH1 0x0b
+        // 9: JUMPI
+        // a: STOP
+        // b: JUMPDEST
+        // c: STOP
+        let code = compare_tracing_code("0x60426000351815600b57005b00", CompareTracing::CmpLog);
+        assert_eq!(
+            cmplog_sites(&code),
+            vec![("0x5".to_string(), "eevm::Opcode::XOR".to_string())]
+        );
+        assert!(code.contains("TRACE_CMPLOG(0x5, eevm::Opcode::XOR, v_2_0, c_42);"));
+
+        // the dictionary mode traces neither
+        let code = compare_tracing_code(SUB_ISZERO_BYTECODE, CompareTracing::Dictionary);
+        assert!(!code.contains("TRACE_"), "{}", code);
+    }
+
+    #[test]
+    fn cmplog_iszero() {
+        // 0: PUSH1 0x00
+        // 2: CALLDATALOAD
+        // 3: ISZERO
+        // 4: PUSH1 0x08
+        // 6: JUMPI
+        // 7: STOP
+        // 8: JUMPDEST
+        // 9: STOP
+        let bytecode = "0x60003515600857005b00";
+        let code = compare_tracing_code(bytecode, CompareTracing::CmpLog);
+        // ISZERO is traced as comparison with zero
+        assert!(
+            code.contains("TRACE_CMPLOG(0x3, eevm::Opcode::ISZERO, v_1_0, c_0);"),
+            "{}",
+            code
+        );
+        let code = compare_tracing_code(bytecode, CompareTracing::Dictionary);
+        assert!(!code.contains("TRACE_"), "{}", code);
+    }
+
+    #[test]
+    fn cmplog_constant_operands() {
+        // 0: PUSH1 0x42
+        // 2: PUSH1 0x00
+        // 4: CALLDATALOAD
+        // 5: EQ
+        // 6: PUSH1 0x0a
+        // 8: JUMPI
+        // 9: STOP
+        // a: JUMPDEST
+        // b: STOP
+        let bytecode = "0x604260003514600a57005b00";
+        let code = compare_tracing_code(bytecode, CompareTracing::CmpLog);
+        assert!(
+            code.contains("TRACE_CMPLOG(0x5, eevm::Opcode::EQ, v_2_0, c_42);"),
+            "{}",
+            code
+        );
+        // the constant is added to the dictionary instead
+        let code = compare_tracing_code(bytecode, CompareTracing::Dictionary);
+        assert!(!code.contains("TRACE_"), "{}", code);
+
+        // comparisons without constant operands are traced in both modes
+        // 0: PUSH1 0x00
+        // 2: CALLDATALOAD
+        // 3: PUSH1 0x20
+        // 5: CALLDATALOAD
+        // 6: EQ
+        // 7: PUSH1 0x0b
+        // 9: JUMPI
+        // a: STOP
+        // b: JUMPDEST
+        // c: STOP
+        let bytecode = "0x60003560203514600b57005b00";
+        let code = compare_tracing_code(bytecode, CompareTracing::CmpLog);
+        assert!(
+            code.contains("TRACE_CMPLOG(0x6, eevm::Opcode::EQ, v_3_0, v_1_0);"),
+            "{}",
+            code
+        );
+        let code = compare_tracing_code(bytecode, CompareTracing::Dictionary);
+        assert!(
+            code.contains("TRACE_COMP(eevm::Opcode::EQ, v_3_0, v_1_0);"),
+            "{}",
+            code
+        );
+        assert!(!code.contains("TRACE_CMPLOG"), "{}", code);
+    }
+
+    #[test]
+    fn cmplog_site_ids() {
+        // 0: PUSH1 0x42
+        // 2: PUSH1 0x00
+        // 4: CALLDATALOAD
+        // 5: EQ
+        // 6: PUSH1 0x07
+        // 8: PUSH1 0x20
+        // a: CALLDATALOAD
+        // b: LT
+        // c: STOP
+        let bytecode = "0x60426000351460076020351000";
+        let code = compare_tracing_code(bytecode, CompareTracing::CmpLog);
+        // every site is identified by the pc of its instruction
+        let sites = cmplog_sites(&code);
+        assert_eq!(
+            sites,
+            vec![
+                ("0x5".to_string(), "eevm::Opcode::EQ".to_string()),
+                ("0xb".to_string(), "eevm::Opcode::LT".to_string()),
+            ]
+        );
+        // and stays the same when the block is emitted again
+        let again = compare_tracing_code(bytecode, CompareTracing::CmpLog);
+        assert_eq!(cmplog_sites(&again), sites);
+    }
+
+    #[test]
+    fn trace_state_hooks() {
+        // 0: PUSH1 0x01
+        // 2: PUSH1 0x00
+        // 4: SSTORE
+        // 5: PUSH1 0x00
+        // 7: SLOAD
+        // 8: PUSH1 0x00
+        // a: MSTORE
+        // b: STOP
+        let bytecode = hexutil::read_hex("0x600160005560005460005200").unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+        let options = CodegenOptions {
+            trace_state: true,
+            ..Default::default()
+        };
+        let mut globals = BTreeMap::new();
+        let code = emit_basic_block(
+            &program.basic_blocks[0],
+            &program.meta,
+            &mut globals,
+            None,
+            &options,
+        );
+        assert!(code.contains("TRACE_SSTORE(0x4, c_0, c_1);"));
+        assert!(code.contains("TRACE_SLOAD(0x7, c_0);"));
+        assert!(code.contains("TRACE_MEM(0xa, c_0, c_20);"));
+    }
+
+    /// Emits the first basic block of the given bytecode with the external call hooks enabled.
+    fn call_hooks_code(bytecode: &str) -> String {
+        let bytecode = hexutil::read_hex(bytecode).unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+        let options = CodegenOptions {
+            trace_calls: true,
+            ..Default::default()
+        };
+        let mut globals = BTreeMap::new();
+        emit_basic_block(
+            &program.basic_blocks[0],
+            &program.meta,
+            &mut globals,
+            None,
+            &options,
+        )
+    }
+
+    #[test]
+    fn external_call_hooks_call() {
+        // 0: PUSH1 0x00 (retLength)
+        // 2: DUP1 (retOffset)
+        // 3: DUP1 (argsLength)
+        // 4: DUP1 (argsOffset)
+        // 5: PUSH1 0x2a (value)
+        // 7: CALLER (address)
+        // 8: GAS
+        // 9: CALL
+        // a: STOP
+        let code = call_hooks_code("0x6000808080602a335af100");
+        // target is the CALLER result, value the constant
+        assert!(
+            code.contains(
+                "before_external_call(0x9, eevm::Opcode::CALL, v_5_0, c_2a, storage_writes);"
+            ),
+            "{}",
+            code
+        );
+        // success is the result of the CALL, nothing is created
+        assert!(
+            code.contains("after_external_call(0x9, eevm::Opcode::CALL, v_7_0 != 0, c_0);"),
+            "{}",
+            code
+        );
+        // the hooks are emitted around the call, in this order
+        let before = code.find("before_external_call(").unwrap();
+        let call = code.find("const uint256_t v_7_0 = call_v(").unwrap();
+        let after = code.find("after_external_call(").unwrap();
+        assert!(before < call && call < after, "{}", code);
+        // there is no SSTORE, so the write-set stays empty
+        assert!(!code.contains("storage_writes["), "{}", code);
+    }
+
+    #[test]
+    fn external_call_hooks_delegatecall() {
+        // 0: PUSH1 0x00 (retLength)
+        // 2: DUP1 (retOffset)
+        // 3: DUP1 (argsLength)
+        // 4: DUP1 (argsOffset)
+        // 5: CALLER (address)
+        // 6: GAS
+        // 7: DELEGATECALL
+        // 8: STOP
+        let code = call_hooks_code("0x6000808080335af400");
+        // DELEGATECALL has no value operand, the hook gets zero
+        assert!(
+            code.contains(
+                "before_external_call(0x7, eevm::Opcode::DELEGATECALL, v_4_0, c_0, storage_writes);"
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains("after_external_call(0x7, eevm::Opcode::DELEGATECALL, v_6_0 != 0, c_0);"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn external_call_hooks_create2() {
+        // 0: PUSH1 0x07 (salt)
+        // 2: PUSH1 0x00 (length)
+        // 4: DUP1 (offset)
+        // 5: PUSH1 0x2a (value)
+        // 7: CREATE2
+        // 8: STOP
+        let code = call_hooks_code("0x6007600080602af500");
+        // the target is not known before the creation, the value is the endowment
+        assert!(
+            code.contains(
+                "before_external_call(0x7, eevm::Opcode::CREATE2, c_0, c_2a, storage_writes);"
+            ),
+            "{}",
+            code
+        );
+        // the created address is passed separately from the success flag
+        assert!(
+            code.contains("after_external_call(0x7, eevm::Opcode::CREATE2, v_4_0 != 0, v_4_0);"),
+            "{}",
+            code
+        );
+    }
+
+    #[test]
+    fn external_call_hooks_create_and_write_set() {
+        // 0: PUSH1 0x01
+        // 2: PUSH1 0x00
+        // 4: SSTORE
+        // 5: PUSH1 0x00 (length)
+        // 7: DUP1 (offset)
+        // 8: DUP1 (value)
+        // 9: CREATE
+        // a: STOP
+        let code = call_hooks_code("0x600160005560008080f000");
+        // the write-set is keyed by slot and only maintained if the hooks are implemented
+        assert!(
+            code.contains(
+                "#ifdef EVM2CPP_HAS_EXTERNAL_CALL_HOOKS\nstorage_writes[c_0] = c_1;\n#endif\n"
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains(
+                "before_external_call(0x9, eevm::Opcode::CREATE, c_0, c_0, storage_writes);"
+            ),
+            "{}",
+            code
+        );
+        assert!(
+            code.contains("after_external_call(0x9, eevm::Opcode::CREATE, v_6_0 != 0, v_6_0);"),
+            "{}",
+            code
+        );
+
+        // without the hooks, SSTOREs are not recorded at all
+        let bytecode = hexutil::read_hex("0x600160005560008080f000").unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+        let mut globals = BTreeMap::new();
+        let code = emit_basic_block(
+            &program.basic_blocks[0],
+            &program.meta,
+            &mut globals,
+            None,
+            &CodegenOptions::default(),
+        );
+        assert!(!code.contains("storage_writes"), "{}", code);
+        assert!(!code.contains("external_call("), "{}", code);
+    }
+
+    #[test]
+    fn translate_constructor_with_immutable() {
+        use crate::combinedjson::{ImmutableReferences, LinkReference};
+        use crate::linking::{immutable_ranges, link_immutables, Immutables};
+
+        // runtime code: PUSH32 <immutable 7>; STOP
+        let runtime_code = format!("7f{}00", "00".repeat(32));
+        let mut immutable_references = ImmutableReferences::new();
+        immutable_references.insert(
+            "7".to_string(),
+            vec![LinkReference {
+                start: 1,
+                length: 32,
+            }],
+        );
+        let mut immutables = Immutables::default();
+        immutables.add("7=0x2a").unwrap();
+        let (linked, unresolved) =
+            link_immutables(&runtime_code, &immutables, &immutable_references).unwrap();
+        assert!(unresolved.is_empty());
+        let linked = hexutil::read_hex(&linked).unwrap();
+
+        // 0: PUSH1 0x22 (length)
+        // 2: DUP1
+        // 3: PUSH1 0x0c (offset)
+        // 5: PUSH1 0x00
+        // 7: CODECOPY
+        // 8: PUSH1 0x00
+        // a: RETURN
+        // b: INVALID
+        // c: runtime code with the placeholder, followed by the constructor arguments
+        let init_code = hexutil::read_hex(&format!(
+            "0x602280600c6000396000f3fe{}{:064x}",
+            runtime_code, 0x2a
+        ))
+        .unwrap();
+
+        let dir = crate::testutil::TestDir::new("translate_constructor_with_immutable");
+        let output = OutputLayout::out_dir(dir.path()).unwrap();
+        let options = CodegenOptions {
+            translate_constructor: true,
+            ..Default::default()
+        };
+        translate_to_c(
+            &output,
+            "Imm",
+            linked.clone(),
+            Some(init_code.clone()),
+            &immutable_ranges(&immutable_references),
+            &[],
+            &[],
+            None,
+            None,
+            &options,
+        )
+        .unwrap();
+        let code = std::fs::read_to_string(output.contract_source("Imm")).unwrap();
+        let construct = &code[code.find("::construct() {").unwrap()..];
+        let construct = &construct[..construct.find("exit_label:").unwrap()];
+        // only the init code up to the embedded runtime code is translated
+        assert!(construct.contains("pc_b:"), "{}", construct);
+        assert!(!construct.contains("pc_c:"), "{}", construct);
+        assert!(!construct.contains("pc_2d:"), "{}", construct);
+
+        // without the immutable ranges, the runtime code is not found
+        let err = translate_to_c(
+            &output,
+            "Imm",
+            linked,
+            Some(init_code),
+            &[],
+            &[],
+            &[],
+            None,
+            None,
+            &options,
+        )
+        .unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("runtime code is not part of the constructor bytecode"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn source_annotations_per_group() {
+        use crate::sourcemap::{
+            resolve_source_map, JumpType, RawSourceMapEntry, SourceFile, SourceFiles,
+        };
+        use std::rc::Rc;
+
+        let bytecode = hexutil::read_hex("0x600160005560005460005200").unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+        let raw = |byte_offset, length, file_index| RawSourceMapEntry {
+            byte_offset,
+            length,
+            file_index,
+            jump_type: JumpType::Jump,
+            modifier_depth: 0,
+        };
+        let mut files = SourceFiles::new();
+        files.insert(
+            0,
+            SourceFile {
+                name: Rc::new("a.sol".to_string()),
+                contents: "x = 1;\ny = x;\n".to_string(),
+                generated: false,
+            },
+        );
+        files.insert(
+            1,
+            SourceFile {
+                name: Rc::new("#utility.yul".to_string()),
+                contents: "{ }".to_string(),
+                generated: true,
+            },
+        );
+        let mut entries = vec![raw(0, 6, 0); 3];
+        entries.extend(vec![raw(7, 6, 0); 2]);
+        // the last instructions are not covered by the sourcemap at all
+        entries.push(raw(0, 0, -1));
+        let sm = resolve_source_map(&entries, &files).unwrap();
+
+        let options = CodegenOptions {
+            line_directives: true,
+            ..Default::default()
+        };
+        let mut globals = BTreeMap::new();
+        let code = emit_basic_block(
+            &program.basic_blocks[0],
+            &program.meta,
+            &mut globals,
+            Some(&sm),
+            &options,
+        );
+        assert_eq!(
+            code.matches("/****** Source a.sol:1:1-1:7 ******\nx = 1;\n")
+                .count(),
+            1
+        );
+        assert_eq!(
+            code.matches("/****** Source a.sol:2:1-2:7 ******\ny = x;\n")
+                .count(),
+            1
+        );
+        assert_eq!(code.matches("/* no source */").count(), 1);
+        assert_eq!(code.matches("#line 1 \"a.sol\"").count(), 1);
+        assert_eq!(code.matches("#line 2 \"a.sol\"").count(), 1);
+        assert!(!code.contains("/* compiler-generated code */"));
+
+        // a block that only consists of compiler-generated code is labeled as such
+        let sm = resolve_source_map(&[raw(0, 3, 1)], &files).unwrap();
+        let mut globals = BTreeMap::new();
+        let code = emit_basic_block(
+            &program.basic_blocks[0],
+            &program.meta,
+            &mut globals,
+            Some(&sm),
+            &options,
+        );
+        assert!(code.contains("/* compiler-generated code */"));
+        assert!(code.contains("/****** Compiler-generated Source #utility.yul:1:1-1:4 ******"));
+        assert!(!code.contains("#line"));
+    }
+
+    #[test]
+    fn line_resets_point_to_generated_file() {
+        let code = format!("a\n#line 3 \"x.sol\"\nb\n{}\nc\n", LINE_RESET_MARKER);
+        let resolved = resolve_line_resets(&code, 10, "out.cpp");
+        assert_eq!(
+            resolved,
+            "a\n#line 3 \"x.sol\"\nb\n#line 14 \"out.cpp\"\nc\n"
+        );
+
+        // clang-format joins/splits lines, the resets are renumbered afterwards
+        let formatted = "x\n#line 3 \"x.sol\"\nb c\n  #line 14 \"out.cpp\"\nc\n";
+        assert_eq!(
+            renumber_line_resets(formatted, "out.cpp"),
+            "x\n#line 3 \"x.sol\"\nb c\n#line 5 \"out.cpp\"\nc\n"
+        );
+    }
 }
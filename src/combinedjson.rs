@@ -14,9 +14,11 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use crate::vyper::{is_vyper_combined_json, read_vyper_output};
 use anyhow::Context;
 use serde::{Deserialize, Deserializer, Serialize};
 use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
 
 // TODO: a combined parser for the combined json and the sourcemap?
 //use crate::sourcemap::{parse_source_map, SourceMap};
@@ -49,6 +51,32 @@ where
     })
 }
 
+/// A source generated by the compiler, e.g., the Yul utility functions for ABI en/decoding, which
+/// newer solc versions emit alongside the sourcemaps. The `id` is the source index that is used in
+/// the sourcemap and continues after the indices of the source list.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GeneratedSource {
+    pub id: i32,
+    pub name: String,
+    pub contents: String,
+    #[serde(default)]
+    pub language: String,
+}
+
+/// Position of a library placeholder in the bytecode (in bytes).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkReference {
+    pub start: usize,
+    pub length: usize,
+}
+
+/// Positions of the library placeholders by source file and library name.
+pub type LinkReferences = BTreeMap<String, BTreeMap<String, Vec<LinkReference>>>;
+
+/// Positions of the placeholders of immutable variables in the runtime code by the AST id of the
+/// variable.
+pub type ImmutableReferences = BTreeMap<String, Vec<LinkReference>>;
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Contract {
     #[serde(deserialize_with = "ensures_string")]
@@ -68,6 +96,37 @@ pub struct Contract {
         default
     )]
     pub srcmap_runtime: String,
+    #[serde(
+        rename(deserialize = "generated-sources", serialize = "generated-sources"),
+        default
+    )]
+    pub generated_sources: Vec<GeneratedSource>,
+    #[serde(
+        rename(
+            deserialize = "generated-sources-runtime",
+            serialize = "generated-sources-runtime"
+        ),
+        default
+    )]
+    pub generated_sources_runtime: Vec<GeneratedSource>,
+    #[serde(
+        rename(deserialize = "linkReferences", serialize = "linkReferences"),
+        default
+    )]
+    pub link_references: LinkReferences,
+    #[serde(
+        rename(
+            deserialize = "deployedLinkReferences",
+            serialize = "deployedLinkReferences"
+        ),
+        default
+    )]
+    pub deployed_link_references: LinkReferences,
+    #[serde(
+        rename(deserialize = "immutableReferences", serialize = "immutableReferences"),
+        default
+    )]
+    pub immutable_references: ImmutableReferences,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -80,6 +139,80 @@ pub struct Combined {
 
     #[serde(default)]
     pub version: String,
+
+    /// contents of the source files in the source list, if they are embedded in the input (e.g.,
+    /// in a build-info file); otherwise the source files are read from disk.
+    #[serde(skip)]
+    pub source_contents: BTreeMap<String, String>,
+}
+
+/// The JSON input formats that contain contracts.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum JsonFormat {
+    /// output of `solc --combined-json`
+    Combined,
+    /// a single contract, e.g., a truffle artifact
+    SingleContract,
+    /// a Hardhat artifact, whose sourcemaps are in the build-info file referenced by the
+    /// accompanying `.dbg.json` file
+    Hardhat,
+    /// a Foundry artifact, i.e., `out/<File>.sol/<Contract>.json`
+    Foundry,
+    /// output of `solc --standard-json` or a build-info file of Hardhat/Foundry, which contains
+    /// the standard-JSON input and output
+    StandardJson,
+    /// output of `vyper -f combined_json` (or the plain output of
+    /// `vyper -f bytecode_runtime,abi,source_map`)
+    Vyper,
+}
+
+/// Guesses the format of a JSON file containing contracts.
+pub fn detect_json_format(value: &serde_json::Value) -> Option<JsonFormat> {
+    if value
+        .get("output")
+        .is_some_and(|o| o.get("contracts").is_some())
+    {
+        return Some(JsonFormat::StandardJson);
+    }
+    if let Some(contracts) = value.get("contracts").and_then(|c| c.as_object()) {
+        // combined.json has a flat map of `file:Name` to contract, while standard-JSON is nested
+        // by file and contract name
+        let nested = contracts.values().any(|file| {
+            file.as_object()
+                .is_some_and(|f| f.values().any(|c| c.get("evm").is_some()))
+        });
+        return Some(if nested {
+            JsonFormat::StandardJson
+        } else {
+            JsonFormat::Combined
+        });
+    }
+    if is_vyper_combined_json(value) {
+        return Some(JsonFormat::Vyper);
+    }
+    if value.get("_format").and_then(|f| f.as_str()) == Some(HARDHAT_ARTIFACT_FORMAT) {
+        return Some(JsonFormat::Hardhat);
+    }
+    // Foundry nests the bytecode in objects, while truffle & co. have plain hex strings
+    if value
+        .get("deployedBytecode")
+        .is_some_and(|b| b.get("object").is_some())
+    {
+        return Some(JsonFormat::Foundry);
+    }
+    if value.get("bin").is_some() || value.get("bytecode").is_some() {
+        return Some(JsonFormat::SingleContract);
+    }
+    None
+}
+
+pub fn detect_json_format_of_file(path: &str) -> anyhow::Result<JsonFormat> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read JSON file from: {}", path))?;
+    let value: serde_json::Value =
+        serde_json::from_str(&s).with_context(|| format!("Failed to parse JSON file {}", path))?;
+    detect_json_format(&value)
+        .ok_or_else(|| anyhow!("Failed to detect the format of JSON file {}", path))
 }
 
 pub fn read_combined_from_file(path: &str) -> anyhow::Result<Combined> {
@@ -95,6 +228,420 @@ pub fn read_combined_from_file(path: &str) -> anyhow::Result<Combined> {
     }
 }
 
+#[derive(Debug, Default, Deserialize)]
+struct StandardJsonBytecode {
+    #[serde(default)]
+    object: String,
+    #[serde(rename = "sourceMap", default)]
+    source_map: String,
+    #[serde(rename = "generatedSources", default)]
+    generated_sources: Vec<GeneratedSource>,
+    #[serde(rename = "linkReferences", default)]
+    link_references: LinkReferences,
+    #[serde(rename = "immutableReferences", default)]
+    immutable_references: ImmutableReferences,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StandardJsonEvm {
+    #[serde(default)]
+    bytecode: StandardJsonBytecode,
+    #[serde(rename = "deployedBytecode", default)]
+    deployed_bytecode: StandardJsonBytecode,
+}
+
+#[derive(Debug, Deserialize)]
+struct StandardJsonContract {
+    #[serde(default)]
+    abi: serde_json::Value,
+    #[serde(default)]
+    evm: StandardJsonEvm,
+}
+
+#[derive(Debug, Deserialize)]
+struct StandardJsonSource {
+    id: i32,
+}
+
+#[derive(Debug, Deserialize)]
+struct StandardJsonOutput {
+    #[serde(default)]
+    contracts: BTreeMap<String, BTreeMap<String, StandardJsonContract>>,
+    #[serde(default)]
+    sources: BTreeMap<String, StandardJsonSource>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StandardJsonInputSource {
+    #[serde(default)]
+    content: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct StandardJsonInput {
+    #[serde(default)]
+    sources: BTreeMap<String, StandardJsonInputSource>,
+}
+
+/// Hardhat/Foundry build-info files contain both the standard-JSON input and output.
+#[derive(Debug, Deserialize)]
+struct BuildInfo {
+    #[serde(default)]
+    input: StandardJsonInput,
+    output: StandardJsonOutput,
+    #[serde(rename = "solcVersion", default)]
+    solc_version: String,
+}
+
+impl StandardJsonOutput {
+    /// Converts the standard-JSON output to the combined.json representation, i.e., the contracts
+    /// are identified as `file:Name` and the source list is ordered by source id.
+    fn into_combined(self, version: String) -> anyhow::Result<Combined> {
+        let mut sources: Vec<(i32, String)> = self
+            .sources
+            .into_iter()
+            .map(|(path, source)| (source.id, path))
+            .collect();
+        sources.sort();
+        for (idx, (id, path)) in sources.iter().enumerate() {
+            if *id != idx as i32 {
+                bail!(
+                    "unexpected source id {} for source {} in standard-JSON",
+                    id,
+                    path
+                );
+            }
+        }
+
+        let mut contracts = BTreeMap::new();
+        for (file, file_contracts) in self.contracts.into_iter() {
+            for (name, c) in file_contracts.into_iter() {
+                let abi = match c.abi {
+                    serde_json::Value::Null => "[]".to_string(),
+                    serde_json::Value::String(s) => s,
+                    v => serde_json::to_string(&v)?,
+                };
+                let contract = Contract {
+                    abi,
+                    bin: c.evm.bytecode.object,
+                    bin_runtime: c.evm.deployed_bytecode.object,
+                    srcmap: c.evm.bytecode.source_map,
+                    srcmap_runtime: c.evm.deployed_bytecode.source_map,
+                    generated_sources: c.evm.bytecode.generated_sources,
+                    generated_sources_runtime: c.evm.deployed_bytecode.generated_sources,
+                    link_references: c.evm.bytecode.link_references,
+                    deployed_link_references: c.evm.deployed_bytecode.link_references,
+                    immutable_references: c.evm.deployed_bytecode.immutable_references,
+                };
+                contracts.insert(format!("{}:{}", file, name), contract);
+            }
+        }
+
+        Ok(Combined {
+            contracts,
+            source_list: sources.into_iter().map(|(_, path)| path).collect(),
+            version,
+            source_contents: BTreeMap::new(),
+        })
+    }
+}
+
+/// Reads the output of `solc --standard-json` or a Hardhat/Foundry build-info file, which also
+/// embeds the source files.
+pub fn read_standard_json_from_file(path: &str) -> anyhow::Result<Combined> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read standard-JSON file from: {}", path))?;
+    let value: serde_json::Value = serde_json::from_str(&s)
+        .with_context(|| format!("Failed to parse standard-JSON file {}", path))?;
+    let deserialize_error = |e| anyhow!("Failed to deserialize file {} due to error {:?}", path, e);
+
+    if value.get("output").is_some() {
+        let build_info: BuildInfo = serde_json::from_value(value).map_err(deserialize_error)?;
+        let mut combined = build_info.output.into_combined(build_info.solc_version)?;
+        combined.source_contents = build_info
+            .input
+            .sources
+            .into_iter()
+            .filter_map(|(path, source)| source.content.map(|c| (path, c)))
+            .collect();
+        Ok(combined)
+    } else {
+        let output: StandardJsonOutput =
+            serde_json::from_value(value).map_err(deserialize_error)?;
+        output.into_combined(String::new())
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FoundryBytecode {
+    #[serde(default)]
+    object: String,
+    #[serde(rename = "sourceMap", default)]
+    source_map: String,
+    #[serde(rename = "linkReferences", default)]
+    link_references: LinkReferences,
+    #[serde(rename = "immutableReferences", default)]
+    immutable_references: ImmutableReferences,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FoundrySettings {
+    #[serde(rename = "compilationTarget", default)]
+    compilation_target: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct FoundryMetadata {
+    #[serde(default)]
+    settings: FoundrySettings,
+}
+
+#[derive(Debug, Deserialize)]
+struct FoundryAst {
+    #[serde(rename = "absolutePath")]
+    absolute_path: String,
+}
+
+/// A contract artifact written by Foundry to `out/<File>.sol/<Contract>.json`.
+#[derive(Debug, Deserialize)]
+struct FoundryArtifact {
+    #[serde(default)]
+    abi: serde_json::Value,
+    #[serde(default)]
+    bytecode: FoundryBytecode,
+    #[serde(rename = "deployedBytecode", default)]
+    deployed_bytecode: FoundryBytecode,
+    #[serde(default)]
+    metadata: Option<FoundryMetadata>,
+    #[serde(default)]
+    ast: Option<FoundryAst>,
+}
+
+/// Reads a Foundry artifact and returns the contract together with the path of the source file
+/// it was compiled from (if the artifact contains that information).
+pub fn read_foundry_artifact(path: &Path) -> anyhow::Result<(Contract, Option<String>)> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read Foundry artifact from: {}", path.display()))?;
+    let artifact: FoundryArtifact = serde_json::from_str(&s).map_err(|e| {
+        anyhow!(
+            "Failed to deserialize Foundry artifact {} due to error {:?}",
+            path.display(),
+            e
+        )
+    })?;
+
+    let ast_path = artifact.ast.map(|a| a.absolute_path);
+    let source_path = artifact
+        .metadata
+        .and_then(|m| m.settings.compilation_target.into_keys().next())
+        .or(ast_path);
+    let abi = match artifact.abi {
+        serde_json::Value::Null => "[]".to_string(),
+        v => serde_json::to_string(&v)?,
+    };
+    let contract = Contract {
+        abi,
+        bin: artifact.bytecode.object,
+        bin_runtime: artifact.deployed_bytecode.object,
+        srcmap: artifact.bytecode.source_map,
+        srcmap_runtime: artifact.deployed_bytecode.source_map,
+        generated_sources: vec![],
+        generated_sources_runtime: vec![],
+        link_references: artifact.bytecode.link_references,
+        deployed_link_references: artifact.deployed_bytecode.link_references,
+        immutable_references: artifact.deployed_bytecode.immutable_references,
+    };
+    Ok((contract, source_path))
+}
+
+/// Returns the name of the contract of a Foundry artifact from its file name. Multi-version builds
+/// are named `<Contract>.<solc version>.json`.
+fn foundry_contract_name(artifact: &Path) -> String {
+    let stem = artifact.file_stem().unwrap_or_default().to_string_lossy();
+    stem.split('.').next().unwrap().to_string()
+}
+
+/// Reads a single Foundry artifact as compiler output with one contract, which is identified as
+/// `<source path>:<Contract>` like in `read_foundry_out_dir`.
+pub fn read_foundry_artifact_file(path: &str) -> anyhow::Result<Combined> {
+    let path = Path::new(path);
+    let (contract, source_path) = read_foundry_artifact(path)?;
+    let name = foundry_contract_name(path);
+    let source_path = source_path.unwrap_or_else(|| {
+        path.parent()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_default()
+    });
+    Ok(Combined {
+        contracts: std::iter::once((format!("{}:{}", source_path, name), contract)).collect(),
+        source_list: vec![],
+        version: String::new(),
+        source_contents: BTreeMap::new(),
+    })
+}
+
+/// Reads all contract artifacts in the output directory of a Foundry project, i.e.,
+/// `<out>/<File>.sol/<Contract>.json`. The contracts are identified as `<source path>:<Contract>`.
+/// Foundry artifacts do not contain the source list, so sourcemaps cannot be resolved.
+pub fn read_foundry_out_dir(out_dir: &Path) -> anyhow::Result<Combined> {
+    let mut contracts = BTreeMap::new();
+    let mut source_dirs: Vec<PathBuf> = std::fs::read_dir(out_dir)
+        .with_context(|| {
+            format!(
+                "Failed to read Foundry output directory {}",
+                out_dir.display()
+            )
+        })?
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_dir() && p.file_name().is_some_and(|n| n != "build-info"))
+        .collect();
+    source_dirs.sort();
+
+    for dir in source_dirs.iter() {
+        let mut artifacts: Vec<PathBuf> = std::fs::read_dir(dir)?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|e| e == "json"))
+            .collect();
+        artifacts.sort();
+        for artifact in artifacts.iter() {
+            let name = foundry_contract_name(artifact);
+            let (contract, source_path) = read_foundry_artifact(artifact)?;
+            let source_path = source_path
+                .unwrap_or_else(|| dir.file_name().unwrap().to_string_lossy().into_owned());
+            contracts.insert(format!("{}:{}", source_path, name), contract);
+        }
+    }
+
+    Ok(Combined {
+        contracts,
+        source_list: vec![],
+        version: String::new(),
+        source_contents: BTreeMap::new(),
+    })
+}
+
+const HARDHAT_ARTIFACT_FORMAT: &str = "hh-sol-artifact-1";
+
+/// A contract artifact written by Hardhat to `artifacts/<path>/<File>.sol/<Contract>.json`.
+#[derive(Debug, Deserialize)]
+struct HardhatArtifact {
+    #[serde(rename = "contractName")]
+    contract_name: String,
+    #[serde(rename = "sourceName")]
+    source_name: String,
+    #[serde(default)]
+    abi: serde_json::Value,
+    #[serde(default)]
+    bytecode: String,
+    #[serde(rename = "deployedBytecode", default)]
+    deployed_bytecode: String,
+    #[serde(rename = "linkReferences", default)]
+    link_references: LinkReferences,
+    #[serde(rename = "deployedLinkReferences", default)]
+    deployed_link_references: LinkReferences,
+}
+
+/// The `<Contract>.dbg.json` file next to a Hardhat artifact.
+#[derive(Debug, Deserialize)]
+struct HardhatDebugFile {
+    #[serde(rename = "buildInfo")]
+    build_info: String,
+}
+
+/// Reads a Hardhat artifact. The artifact itself contains only the bytecode, so the sourcemaps,
+/// the source list and the contents of the source files are taken from the build-info file,
+/// which is referenced by the `.dbg.json` file next to the artifact (if it exists).
+pub fn read_hardhat_artifact(path: &str) -> anyhow::Result<Combined> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read Hardhat artifact from: {}", path))?;
+    let artifact: HardhatArtifact = serde_json::from_str(&s).map_err(|e| {
+        anyhow!(
+            "Failed to deserialize Hardhat artifact {} due to error {:?}",
+            path,
+            e
+        )
+    })?;
+    let key = format!("{}:{}", artifact.source_name, artifact.contract_name);
+
+    let dbg_path = PathBuf::from(format!(
+        "{}.dbg.json",
+        path.strip_suffix(".json").unwrap_or(path)
+    ));
+    let mut combined = if dbg_path.exists() {
+        let s = std::fs::read_to_string(&dbg_path)
+            .with_context(|| format!("Failed to read {}", dbg_path.display()))?;
+        let dbg: HardhatDebugFile = serde_json::from_str(&s).map_err(|e| {
+            anyhow!(
+                "Failed to deserialize {} due to error {:?}",
+                dbg_path.display(),
+                e
+            )
+        })?;
+        let build_info_path = dbg_path
+            .parent()
+            .unwrap_or_else(|| Path::new(""))
+            .join(&dbg.build_info);
+        let build_info_path = build_info_path.to_string_lossy();
+        let mut combined = read_standard_json_from_file(&build_info_path)?;
+        if !combined.contracts.contains_key(&key) {
+            bail!(
+                "build-info file {} does not contain the contract {}",
+                build_info_path,
+                key
+            );
+        }
+        combined.contracts.retain(|k, _| *k == key);
+        combined
+    } else {
+        Combined {
+            contracts: BTreeMap::new(),
+            source_list: vec![],
+            version: String::new(),
+            source_contents: BTreeMap::new(),
+        }
+    };
+
+    let abi = match artifact.abi {
+        serde_json::Value::Null => "[]".to_string(),
+        v => serde_json::to_string(&v)?,
+    };
+    // the bytecode of the artifact takes precedence over the build-info
+    let contract = combined.contracts.entry(key).or_insert(Contract {
+        abi: String::new(),
+        bin: String::new(),
+        bin_runtime: String::new(),
+        srcmap: String::new(),
+        srcmap_runtime: String::new(),
+        generated_sources: vec![],
+        generated_sources_runtime: vec![],
+        link_references: LinkReferences::new(),
+        deployed_link_references: LinkReferences::new(),
+        immutable_references: ImmutableReferences::new(),
+    });
+    contract.abi = abi;
+    contract.bin = artifact.bytecode;
+    contract.bin_runtime = artifact.deployed_bytecode;
+    contract.link_references = artifact.link_references;
+    contract.deployed_link_references = artifact.deployed_link_references;
+    Ok(combined)
+}
+
+/// Reads a file with multiple contracts in one of the supported JSON formats.
+pub fn read_contracts_from_file(path: &str, format: JsonFormat) -> anyhow::Result<Combined> {
+    match format {
+        JsonFormat::Combined => read_combined_from_file(path),
+        JsonFormat::StandardJson => read_standard_json_from_file(path),
+        JsonFormat::Hardhat => read_hardhat_artifact(path),
+        JsonFormat::Foundry => read_foundry_artifact_file(path),
+        JsonFormat::Vyper => read_vyper_output(path),
+        JsonFormat::SingleContract => bail!(
+            "{} contains only a single contract and no source information",
+            path
+        ),
+    }
+}
+
 pub fn read_single_contract_combined_from_file(path: &str) -> anyhow::Result<Contract> {
     let s = std::fs::read_to_string(path)
         .with_context(|| format!("Failed to read combined.json file from: {}", path))?;
@@ -206,6 +753,270 @@ mod tests {
         assert_eq!(contract.bin, "00010203040506070809");
     }
 
+    #[test]
+    fn test_deserialize_generated_sources() {
+        let s = "
+{
+      \"abi\": [],
+      \"bin\": \"00\",
+      \"bin-runtime\": \"00\",
+      \"srcmap-runtime\": \"0:10:0:-:0;5:3:1\",
+      \"generated-sources-runtime\": [
+        {
+          \"ast\": { \"nodeType\": \"YulBlock\", \"statements\": [] },
+          \"contents\": \"{ }\",
+          \"id\": 1,
+          \"language\": \"Yul\",
+          \"name\": \"#utility.yul\"
+        }
+      ]
+}
+        ";
+
+        let contract: Contract = serde_json::from_str(s).unwrap();
+
+        assert!(contract.generated_sources.is_empty());
+        assert_eq!(contract.generated_sources_runtime.len(), 1);
+        assert_eq!(contract.generated_sources_runtime[0].id, 1);
+        assert_eq!(contract.generated_sources_runtime[0].name, "#utility.yul");
+        assert_eq!(contract.generated_sources_runtime[0].contents, "{ }");
+    }
+
+    const STANDARD_JSON_OUTPUT: &str = r##"
+{
+  "contracts": {
+    "src/b.sol": {
+      "B": {
+        "abi": [{"type": "constructor", "inputs": []}],
+        "evm": {
+          "bytecode": { "object": "6080", "sourceMap": "0:10:1:-:0" },
+          "deployedBytecode": {
+            "object": "6001",
+            "sourceMap": "0:5:1:-:0;;5:3:2",
+            "generatedSources": [
+              { "ast": {}, "contents": "{ }", "id": 2, "language": "Yul", "name": "#utility.yul" }
+            ],
+            "immutableReferences": { "5": [ { "start": 1, "length": 32 } ] }
+          }
+        }
+      }
+    },
+    "src/a.sol": {
+      "I": { "abi": [] }
+    }
+  },
+  "sources": {
+    "src/a.sol": { "id": 0 },
+    "src/b.sol": { "id": 1 }
+  }
+}
+"##;
+
+    #[test]
+    fn test_standard_json() {
+        let value: serde_json::Value = serde_json::from_str(STANDARD_JSON_OUTPUT).unwrap();
+        assert_eq!(detect_json_format(&value), Some(JsonFormat::StandardJson));
+        let output: StandardJsonOutput = serde_json::from_value(value).unwrap();
+        let c = output.into_combined(String::new()).unwrap();
+
+        assert_eq!(c.source_list, vec!["src/a.sol", "src/b.sol"]);
+        assert_eq!(c.contracts.len(), 2);
+        let b = &c.contracts["src/b.sol:B"];
+        assert_eq!(b.bin, "6080");
+        assert_eq!(b.bin_runtime, "6001");
+        assert_eq!(b.srcmap, "0:10:1:-:0");
+        assert_eq!(b.srcmap_runtime, "0:5:1:-:0;;5:3:2");
+        assert_eq!(b.generated_sources_runtime[0].id, 2);
+        assert_eq!(b.immutable_references["5"][0].start, 1);
+        assert!(b.abi.contains("constructor"));
+        // interfaces have no bytecode
+        assert_eq!(c.contracts["src/a.sol:I"].bin_runtime, "");
+    }
+
+    #[test]
+    fn test_build_info() {
+        let build_info = format!(
+            r#"{{
+  "id": "0123",
+  "solcVersion": "0.8.19",
+  "input": {{
+    "language": "Solidity",
+    "sources": {{ "src/a.sol": {{ "content": "interface I {{}}" }}, "src/b.sol": {{ "content": "contract B {{}}" }} }}
+  }},
+  "output": {}
+}}"#,
+            STANDARD_JSON_OUTPUT
+        );
+        let value: serde_json::Value = serde_json::from_str(&build_info).unwrap();
+        assert_eq!(detect_json_format(&value), Some(JsonFormat::StandardJson));
+
+        let dir = crate::testutil::TestDir::new("standard_json_build_info");
+        let path = dir.join("build_info.json");
+        std::fs::write(&path, &build_info).unwrap();
+        let c = read_standard_json_from_file(path.to_str().unwrap()).unwrap();
+        assert_eq!(c.version, "0.8.19");
+        assert_eq!(c.source_contents["src/b.sol"], "contract B {}");
+        assert_eq!(c.contracts.len(), 2);
+    }
+
+    #[test]
+    fn test_foundry_out_dir() {
+        let dir = crate::testutil::TestDir::new("foundry_out_dir");
+        let out = dir.path();
+        std::fs::create_dir_all(out.join("A.sol")).unwrap();
+        std::fs::create_dir_all(out.join("build-info")).unwrap();
+        std::fs::write(
+            out.join("A.sol/A.json"),
+            r#"{
+  "abi": [],
+  "bytecode": { "object": "0x6080", "sourceMap": "0:1:0:-:0", "linkReferences": {} },
+  "deployedBytecode": { "object": "0x6001", "sourceMap": "0:1:0:-:0", "linkReferences": {} },
+  "metadata": { "settings": { "compilationTarget": { "src/A.sol": "A" } } },
+  "id": 3
+}"#,
+        )
+        .unwrap();
+        // multi-version build without metadata
+        std::fs::write(
+            out.join("A.sol/B.0.8.19.json"),
+            r#"{ "abi": [], "bytecode": { "object": "0x" }, "deployedBytecode": { "object": "0x" } }"#,
+        )
+        .unwrap();
+        std::fs::write(out.join("build-info/0123.json"), "{}").unwrap();
+
+        let c = read_foundry_out_dir(out).unwrap();
+        assert_eq!(c.contracts.len(), 2);
+        let a = &c.contracts["src/A.sol:A"];
+        assert_eq!(a.bin, "0x6080");
+        assert_eq!(a.bin_runtime, "0x6001");
+        assert_eq!(a.srcmap_runtime, "0:1:0:-:0");
+        assert_eq!(a.abi, "[]");
+        assert!(c.contracts.contains_key("A.sol:B"));
+    }
+
+    #[test]
+    fn test_hardhat_artifact() {
+        let dir = crate::testutil::TestDir::new("hardhat_artifact");
+        let artifact_dir = dir.join("artifacts/src/b.sol");
+        std::fs::create_dir_all(&artifact_dir).unwrap();
+        std::fs::create_dir_all(dir.join("artifacts/build-info")).unwrap();
+
+        let build_info = format!(
+            r#"{{ "solcVersion": "0.8.19", "input": {{ "sources": {{ "src/a.sol": {{ "content": "a" }}, "src/b.sol": {{ "content": "b" }} }} }}, "output": {} }}"#,
+            STANDARD_JSON_OUTPUT
+        );
+        std::fs::write(dir.join("artifacts/build-info/0123.json"), build_info).unwrap();
+        std::fs::write(
+            artifact_dir.join("B.dbg.json"),
+            r#"{ "_format": "hh-sol-dbg-1", "buildInfo": "../../build-info/0123.json" }"#,
+        )
+        .unwrap();
+        let artifact = r#"{
+  "_format": "hh-sol-artifact-1",
+  "contractName": "B",
+  "sourceName": "src/b.sol",
+  "abi": [],
+  "bytecode": "0x6080",
+  "deployedBytecode": "0x6001",
+  "linkReferences": {},
+  "deployedLinkReferences": {}
+}"#;
+        let artifact_path = artifact_dir.join("B.json");
+        std::fs::write(&artifact_path, artifact).unwrap();
+        let artifact_path = artifact_path.to_str().unwrap();
+
+        assert_eq!(
+            detect_json_format_of_file(artifact_path).unwrap(),
+            JsonFormat::Hardhat
+        );
+        let c = read_hardhat_artifact(artifact_path).unwrap();
+        assert_eq!(c.contracts.len(), 1);
+        assert_eq!(c.source_list, vec!["src/a.sol", "src/b.sol"]);
+        assert_eq!(c.source_contents["src/b.sol"], "b");
+        let b = &c.contracts["src/b.sol:B"];
+        assert_eq!(b.bin_runtime, "0x6001");
+        assert_eq!(b.srcmap_runtime, "0:5:1:-:0;;5:3:2");
+        assert_eq!(b.generated_sources_runtime.len(), 1);
+
+        // without the debug file there is no source information
+        std::fs::remove_file(artifact_dir.join("B.dbg.json")).unwrap();
+        let c = read_hardhat_artifact(artifact_path).unwrap();
+        assert!(c.source_list.is_empty());
+        assert_eq!(c.contracts["src/b.sol:B"].srcmap_runtime, "");
+    }
+
+    #[test]
+    fn test_detect_json_format() {
+        let combined = serde_json::json!({
+            "contracts": { "a.sol:A": { "abi": [], "bin": "", "bin-runtime": "" } },
+            "sourceList": ["a.sol"]
+        });
+        assert_eq!(detect_json_format(&combined), Some(JsonFormat::Combined));
+
+        let standard_json: serde_json::Value = serde_json::from_str(STANDARD_JSON_OUTPUT).unwrap();
+        assert_eq!(
+            detect_json_format(&standard_json),
+            Some(JsonFormat::StandardJson)
+        );
+        let build_info = serde_json::json!({ "input": {}, "output": standard_json });
+        assert_eq!(
+            detect_json_format(&build_info),
+            Some(JsonFormat::StandardJson)
+        );
+
+        let vyper = serde_json::json!({
+            "foo.vy": { "bytecode_runtime": "0x", "source_map": {} },
+            "version": "0.3.7"
+        });
+        assert_eq!(detect_json_format(&vyper), Some(JsonFormat::Vyper));
+
+        let hardhat = serde_json::json!({
+            "_format": HARDHAT_ARTIFACT_FORMAT,
+            "bytecode": "0x6080",
+            "deployedBytecode": "0x6001"
+        });
+        assert_eq!(detect_json_format(&hardhat), Some(JsonFormat::Hardhat));
+
+        let foundry = serde_json::json!({
+            "abi": [],
+            "bytecode": { "object": "0x6080" },
+            "deployedBytecode": { "object": "0x6001", "sourceMap": "" }
+        });
+        assert_eq!(detect_json_format(&foundry), Some(JsonFormat::Foundry));
+
+        let truffle = serde_json::json!({ "abi": [], "bytecode": "", "deployedBytecode": "" });
+        assert_eq!(
+            detect_json_format(&truffle),
+            Some(JsonFormat::SingleContract)
+        );
+        let single = serde_json::json!({ "abi": [], "bin": "", "bin-runtime": "" });
+        assert_eq!(
+            detect_json_format(&single),
+            Some(JsonFormat::SingleContract)
+        );
+
+        assert_eq!(detect_json_format(&serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_foundry_artifact_file() {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("contracts/fixtures/verifier.json");
+        let path = path.to_str().unwrap();
+        assert_eq!(
+            detect_json_format_of_file(path).unwrap(),
+            JsonFormat::Foundry
+        );
+        let c = read_contracts_from_file(path, JsonFormat::Foundry).unwrap();
+        assert_eq!(
+            c.contracts.keys().collect::<Vec<_>>(),
+            vec!["src/verifier.sol:verifier"]
+        );
+        let contract = &c.contracts["src/verifier.sol:verifier"];
+        assert!(contract.bin_runtime.starts_with("0x6080"));
+        assert!(!contract.srcmap_runtime.is_empty());
+    }
+
     #[test]
     fn test_contract_deserialize_altnames() {
         let s = "
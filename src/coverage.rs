@@ -0,0 +1,389 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+//! Attribute basic-block coverage (as reported by `on_bb_start`) to solidity source lines and
+//! export it in the LCOV or Cobertura format.
+
+use anyhow::Context;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::analysis::{Operand, Program};
+use crate::instructions::Instruction;
+use crate::sourcemap::{instruction_location, SourceMap};
+
+/// Coverage of a single JUMPI. The counts are the number of times the jump was taken and the
+/// number of times execution fell through to the next instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BranchCoverage {
+    pub line: usize,
+    /// address of the JUMPI instruction
+    pub address: usize,
+    /// `None` if the basic block containing the JUMPI was never executed
+    pub taken: Option<u64>,
+    pub not_taken: Option<u64>,
+}
+
+impl BranchCoverage {
+    /// number of branch directions (out of two) that were executed at least once
+    fn directions_hit(&self) -> usize {
+        self.taken.map_or(0, |t| (t > 0) as usize) + self.not_taken.map_or(0, |t| (t > 0) as usize)
+    }
+}
+
+/// Line and branch coverage of one source file.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileCoverage {
+    /// line number to hit count
+    pub lines: BTreeMap<usize, u64>,
+    pub branches: Vec<BranchCoverage>,
+}
+
+impl FileCoverage {
+    fn lines_hit(&self) -> usize {
+        self.lines.values().filter(|&&h| h > 0).count()
+    }
+
+    fn branches_hit(&self) -> usize {
+        self.branches.iter().map(|b| b.directions_hit()).sum()
+    }
+}
+
+/// Coverage of all source files, keyed by the path of the source file.
+pub type Coverage = BTreeMap<String, FileCoverage>;
+
+fn parse_address(s: &str) -> anyhow::Result<usize> {
+    let r = if let Some(hex) = s.strip_prefix("0x") {
+        usize::from_str_radix(hex, 16)
+    } else {
+        s.parse()
+    };
+    r.with_context(|| format!("invalid basic block address {:?}", s))
+}
+
+/// Reads a list of basic block addresses as written by `translate_to_c` to the `.bb_list` file.
+pub fn read_block_list(path: &str) -> anyhow::Result<Vec<usize>> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read basic block list from {}", path))?;
+    let mut blocks = s
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty())
+        .map(parse_address)
+        .collect::<anyhow::Result<Vec<usize>>>()?;
+    blocks.sort_unstable();
+    blocks.dedup();
+    Ok(blocks)
+}
+
+/// Reads the addresses of executed basic blocks. Every line contains one address (hex with `0x`
+/// prefix or decimal), optionally followed by a hit count. Lines starting with `#` are ignored.
+/// Addresses that are listed multiple times are accumulated.
+pub fn read_hits(path: &str) -> anyhow::Result<BTreeMap<usize, u64>> {
+    let s = std::fs::read_to_string(path)
+        .with_context(|| format!("failed to read basic block hits from {}", path))?;
+    let mut hits = BTreeMap::new();
+    for (lineno, line) in s.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut fields = line.split_whitespace();
+        let address = parse_address(fields.next().unwrap())
+            .with_context(|| format!("{}:{}", path, lineno + 1))?;
+        let count: u64 = if let Some(c) = fields.next() {
+            c.parse()
+                .with_context(|| format!("{}:{}: invalid hit count {:?}", path, lineno + 1, c))?
+        } else {
+            1
+        };
+        *hits.entry(address).or_insert(0) += count;
+    }
+    Ok(hits)
+}
+
+/// Attribute the basic block hits to source lines. The `blocks` are the basic block addresses
+/// from the `.bb_list` file; every instruction between two block addresses belongs to the first
+/// block.
+///
+/// Branch coverage is computed for every JUMPI: the fall-through count is the hit count of the
+/// following basic block and the taken count is the hit count of the jump target, if it is a
+/// constant. Otherwise it is derived from the difference of the hit counts of the block and the
+/// fall-through block. Both are approximations, since the successor blocks might also be reached
+/// via other paths.
+pub fn compute_coverage(
+    program: &Program,
+    blocks: &[usize],
+    hits: &BTreeMap<usize, u64>,
+    sourcemap: &SourceMap,
+) -> Coverage {
+    let block_hits = |address: usize| -> u64 { hits.get(&address).cloned().unwrap_or(0) };
+    // maps every instruction address to the start address of its block
+    let block_of = |address: usize| -> Option<usize> {
+        match blocks.binary_search(&address) {
+            Ok(i) => Some(blocks[i]),
+            Err(0) => None,
+            Err(i) => Some(blocks[i - 1]),
+        }
+    };
+
+    let mut coverage = Coverage::new();
+    for bb in program.basic_blocks.iter() {
+        for inst in bb.instructions.iter() {
+            let location = match instruction_location(sourcemap, inst) {
+                Some(l) if !l.generated => l,
+                // not mapped to any source or to a compiler-generated source, which does not exist
+                // on disk
+                _ => continue,
+            };
+            let block = if let Some(b) = block_of(inst.address) {
+                b
+            } else {
+                continue;
+            };
+            let count = block_hits(block);
+
+            let file_cov = coverage.entry(location.file.to_string()).or_default();
+            let line_hits = file_cov.lines.entry(location.start_line).or_insert(0);
+            // a line might span multiple basic blocks, we count how often the line was entered
+            // at least
+            *line_hits = std::cmp::max(*line_hits, count);
+
+            if inst.opcode == Ok(Instruction::JUMPI) {
+                let branch = if count == 0 {
+                    BranchCoverage {
+                        line: location.start_line,
+                        address: inst.address,
+                        taken: None,
+                        not_taken: None,
+                    }
+                } else {
+                    let not_taken = std::cmp::min(block_hits(inst.address + 1), count);
+                    let target = inst.operands.as_ref().map(|o| o[0]);
+                    let taken = match target {
+                        Some(Operand::Constant((_, t))) if t.bits() <= 64 => {
+                            std::cmp::min(block_hits(t.low_u64() as usize), count)
+                        }
+                        _ => count - not_taken,
+                    };
+                    BranchCoverage {
+                        line: location.start_line,
+                        address: inst.address,
+                        taken: Some(taken),
+                        not_taken: Some(not_taken),
+                    }
+                };
+                file_cov.branches.push(branch);
+            }
+        }
+    }
+    coverage
+}
+
+fn format_branch_count(c: Option<u64>) -> String {
+    c.map_or("-".to_string(), |c| c.to_string())
+}
+
+/// Write the coverage in the LCOV tracefile format (as consumed by `genhtml`).
+pub fn write_lcov<W: Write>(w: &mut W, test_name: &str, coverage: &Coverage) -> anyhow::Result<()> {
+    writeln!(w, "TN:{}", test_name)?;
+    for (file, file_cov) in coverage.iter() {
+        writeln!(w, "SF:{}", file)?;
+        for branch in file_cov.branches.iter() {
+            writeln!(
+                w,
+                "BRDA:{},{},0,{}",
+                branch.line,
+                branch.address,
+                format_branch_count(branch.taken)
+            )?;
+            writeln!(
+                w,
+                "BRDA:{},{},1,{}",
+                branch.line,
+                branch.address,
+                format_branch_count(branch.not_taken)
+            )?;
+        }
+        writeln!(w, "BRF:{}", file_cov.branches.len() * 2)?;
+        writeln!(w, "BRH:{}", file_cov.branches_hit())?;
+        for (line, hits) in file_cov.lines.iter() {
+            writeln!(w, "DA:{},{}", line, hits)?;
+        }
+        writeln!(w, "LF:{}", file_cov.lines.len())?;
+        writeln!(w, "LH:{}", file_cov.lines_hit())?;
+        writeln!(w, "end_of_record")?;
+    }
+    Ok(())
+}
+
+fn rate(covered: usize, valid: usize) -> f64 {
+    if valid == 0 {
+        1.0
+    } else {
+        covered as f64 / valid as f64
+    }
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Write the coverage in the Cobertura XML format.
+pub fn write_cobertura<W: Write>(
+    w: &mut W,
+    package_name: &str,
+    coverage: &Coverage,
+) -> anyhow::Result<()> {
+    let lines_valid: usize = coverage.values().map(|f| f.lines.len()).sum();
+    let lines_covered: usize = coverage.values().map(|f| f.lines_hit()).sum();
+    let branches_valid: usize = coverage.values().map(|f| f.branches.len() * 2).sum();
+    let branches_covered: usize = coverage.values().map(|f| f.branches_hit()).sum();
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+
+    writeln!(w, "<?xml version=\"1.0\" ?>")?;
+    writeln!(
+        w,
+        "<!DOCTYPE coverage SYSTEM 'http://cobertura.sourceforge.net/xml/coverage-04.dtd'>"
+    )?;
+    writeln!(
+        w,
+        "<coverage line-rate=\"{:.4}\" branch-rate=\"{:.4}\" lines-covered=\"{}\" lines-valid=\"{}\" branches-covered=\"{}\" branches-valid=\"{}\" complexity=\"0\" version=\"evm2cpp {}\" timestamp=\"{}\">",
+        rate(lines_covered, lines_valid),
+        rate(branches_covered, branches_valid),
+        lines_covered,
+        lines_valid,
+        branches_covered,
+        branches_valid,
+        env!("CARGO_PKG_VERSION"),
+        timestamp
+    )?;
+    writeln!(w, "  <sources><source>.</source></sources>")?;
+    writeln!(w, "  <packages>")?;
+    writeln!(
+        w,
+        "    <package name=\"{}\" line-rate=\"{:.4}\" branch-rate=\"{:.4}\" complexity=\"0\">",
+        xml_escape(package_name),
+        rate(lines_covered, lines_valid),
+        rate(branches_covered, branches_valid),
+    )?;
+    writeln!(w, "      <classes>")?;
+    for (file, file_cov) in coverage.iter() {
+        writeln!(
+            w,
+            "        <class name=\"{0}\" filename=\"{0}\" line-rate=\"{1:.4}\" branch-rate=\"{2:.4}\" complexity=\"0\">",
+            xml_escape(file),
+            rate(file_cov.lines_hit(), file_cov.lines.len()),
+            rate(file_cov.branches_hit(), file_cov.branches.len() * 2),
+        )?;
+        writeln!(w, "          <methods/>")?;
+        writeln!(w, "          <lines>")?;
+        for (line, hits) in file_cov.lines.iter() {
+            let branches: Vec<&BranchCoverage> = file_cov
+                .branches
+                .iter()
+                .filter(|b| b.line == *line)
+                .collect();
+            if branches.is_empty() {
+                writeln!(
+                    w,
+                    "            <line number=\"{}\" hits=\"{}\" branch=\"false\"/>",
+                    line, hits
+                )?;
+            } else {
+                let total = branches.len() * 2;
+                let covered: usize = branches.iter().map(|b| b.directions_hit()).sum();
+                writeln!(
+                    w,
+                    "            <line number=\"{}\" hits=\"{}\" branch=\"true\" condition-coverage=\"{}% ({}/{})\"/>",
+                    line,
+                    hits,
+                    covered * 100 / total,
+                    covered,
+                    total
+                )?;
+            }
+        }
+        writeln!(w, "          </lines>")?;
+        writeln!(w, "        </class>")?;
+    }
+    writeln!(w, "      </classes>")?;
+    writeln!(w, "    </package>")?;
+    writeln!(w, "  </packages>")?;
+    writeln!(w, "</coverage>")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sourcemap::parse_source_map;
+
+    #[test]
+    fn jumpi_branch_coverage() {
+        // 0: PUSH1 0x00
+        // 2: CALLDATALOAD
+        // 3: PUSH1 0x08
+        // 5: JUMPI
+        // 6: STOP
+        // 7: STOP
+        // 8: JUMPDEST
+        // 9: STOP
+        let bytecode = hexutil::read_hex("0x60003560085700005b00").unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+
+        let dir = crate::testutil::TestDir::new("coverage");
+        let source_path = dir.join("test.sol");
+        std::fs::write(&source_path, "a\nb\nc\n").unwrap();
+        let sourcemap = parse_source_map(
+            "0:1:0:-:0;2:1:0:-:0;;;4:1:0:-:0;;;",
+            &[source_path.to_str().unwrap()],
+            &[],
+        )
+        .unwrap();
+
+        let blocks = vec![0, 6, 7, 8];
+        let hits: BTreeMap<usize, u64> = vec![(0, 2), (6, 1), (8, 1)].into_iter().collect();
+        let coverage = compute_coverage(&program, &blocks, &hits, &sourcemap);
+        let file_cov = &coverage[source_path.to_str().unwrap()];
+
+        assert_eq!(file_cov.lines.get(&1), Some(&2));
+        assert_eq!(file_cov.lines.get(&2), Some(&2));
+        assert_eq!(file_cov.lines.get(&3), Some(&1));
+        assert_eq!(
+            file_cov.branches,
+            vec![BranchCoverage {
+                line: 2,
+                address: 5,
+                taken: Some(1),
+                not_taken: Some(1),
+            }]
+        );
+
+        let mut lcov = Vec::new();
+        write_lcov(&mut lcov, "test", &coverage).unwrap();
+        let lcov = String::from_utf8(lcov).unwrap();
+        assert!(lcov.contains("BRDA:2,5,0,1\nBRDA:2,5,1,1\n"));
+        assert!(lcov.contains("LF:3\nLH:3\n"));
+    }
+}
@@ -0,0 +1,315 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::analysis::{IInstruction, Operand, Program};
+use crate::asm::mnemonic;
+use crate::instructions::Instruction;
+use crate::sourcemap::{instruction_entry, SourceMap};
+use ethereum_types::U256;
+use serde_json::{json, Value};
+
+/// Maximum length of the source snippets in the listing.
+const SNIPPET_LEN: usize = 60;
+
+/// Returns the range of the CBOR encoded metadata that solc (and vyper) append to the runtime
+/// code. The length of the metadata is stored in the last two bytes of the code.
+pub fn metadata_range(bytecode: &[u8]) -> Option<std::ops::Range<usize>> {
+    if bytecode.len() < 2 {
+        return None;
+    }
+    let len = u16::from_be_bytes([bytecode[bytecode.len() - 2], bytecode[bytecode.len() - 1]]);
+    let start = bytecode.len().checked_sub(2 + len as usize)?;
+    // the metadata is a CBOR map with one to five entries (e.g., ipfs, solc, bzzr0, ...)
+    match bytecode.get(start) {
+        Some(0xa1..=0xa5) if len > 0 => Some(start..bytecode.len()),
+        _ => None,
+    }
+}
+
+/// An instruction of the disassembly listing.
+struct Line<'a> {
+    pc: usize,
+    bytes: &'a [u8],
+    mnemonic: String,
+    push_data: Option<&'a [u8]>,
+    block_start: bool,
+    jumpdest: bool,
+    /// folded constant operands (`None` for operands that are only known at runtime)
+    operands: Vec<Option<U256>>,
+    /// folded result of the instruction
+    value: Option<U256>,
+    /// source location and snippet
+    source: Option<(String, String)>,
+}
+
+fn to_hex_string(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn snippet(source: &str) -> String {
+    let line = source.lines().next().unwrap_or_default().trim();
+    if line.chars().count() > SNIPPET_LEN {
+        format!("{}...", line.chars().take(SNIPPET_LEN).collect::<String>())
+    } else {
+        line.to_string()
+    }
+}
+
+fn listing_line<'a>(
+    program: &'a Program,
+    inst: &IInstruction,
+    block_start: bool,
+    sourcemap: Option<&SourceMap>,
+    constants: bool,
+) -> Line<'a> {
+    let bytecode = &program.bytecode;
+    let push_bytes = inst.opcode.ok().and_then(|i| i.push_bytes()).unwrap_or(0);
+    let end = (inst.address + 1 + push_bytes).min(bytecode.len());
+    let is_push = inst.opcode.is_ok_and(|i| i.is_push());
+
+    let (operands, value) = if constants {
+        let operands = inst
+            .operands
+            .iter()
+            .flatten()
+            .map(|o| match o {
+                Operand::Constant((_, v)) => Some(*v),
+                _ => None,
+            })
+            .collect();
+        let value = if is_push {
+            None
+        } else {
+            inst.value.as_ref().and_then(|v| v.first().cloned())
+        };
+        (operands, value)
+    } else {
+        (vec![], None)
+    };
+
+    let source = sourcemap
+        .and_then(|sm| instruction_entry(sm, inst))
+        .and_then(|e| {
+            e.location
+                .as_ref()
+                .map(|l| (l.to_string(), snippet(&e.source)))
+        });
+
+    Line {
+        pc: inst.address,
+        bytes: &bytecode[inst.address..end],
+        mnemonic: mnemonic(inst.opcode),
+        push_data: if is_push {
+            Some(&bytecode[inst.address + 1..end])
+        } else {
+            None
+        },
+        block_start,
+        jumpdest: inst.opcode == Ok(Instruction::JUMPDEST),
+        operands,
+        value,
+        source,
+    }
+}
+
+/// Collects the lines of the listing up to the metadata.
+fn listing_lines<'a>(
+    program: &'a Program,
+    sourcemap: Option<&SourceMap>,
+    constants: bool,
+) -> Vec<Line<'a>> {
+    let metadata_start = metadata_range(&program.bytecode).map(|r| r.start);
+    let mut lines = vec![];
+    for bb in program.basic_blocks.iter() {
+        for (idx, inst) in bb.instructions.iter().enumerate() {
+            if metadata_start.is_some_and(|start| inst.address >= start) {
+                return lines;
+            }
+            lines.push(listing_line(program, inst, idx == 0, sourcemap, constants));
+        }
+    }
+    lines
+}
+
+/// Renders the disassembly listing as text. Basic blocks are separated by an empty line and
+/// annotations (folded constants, source locations) are added as comments.
+pub fn listing_text(program: &Program, sourcemap: Option<&SourceMap>, constants: bool) -> String {
+    let mut text = String::new();
+    for line in listing_lines(program, sourcemap, constants) {
+        if line.block_start {
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text += &format!(
+                "; ---- basic block {:#06x}{}\n",
+                line.pc,
+                if line.jumpdest { " (jump target)" } else { "" }
+            );
+        }
+        let instruction = match line.push_data {
+            Some(data) => format!("{} 0x{}", line.mnemonic, to_hex_string(data)),
+            None => line.mnemonic.clone(),
+        };
+        let mut comments = vec![];
+        if line.operands.iter().any(|o| o.is_some()) {
+            let operands: Vec<String> = line
+                .operands
+                .iter()
+                .map(|o| match o {
+                    Some(v) => format!("{:#x}", v),
+                    None => "_".to_string(),
+                })
+                .collect();
+            comments.push(format!("args: {}", operands.join(", ")));
+        }
+        if let Some(value) = line.value {
+            comments.push(format!("= {:#x}", value));
+        }
+        if let Some((location, snippet)) = line.source.as_ref() {
+            comments.push(format!("{}: {}", location, snippet).trim_end().to_string());
+        }
+
+        let l = format!(
+            "{:#06x}  {:<14} {}",
+            line.pc,
+            to_hex_string(line.bytes),
+            instruction
+        );
+        if comments.is_empty() {
+            text += &format!("{}\n", l);
+        } else {
+            text += &format!("{:<48} ; {}\n", l, comments.join(" | "));
+        }
+    }
+    if let Some(metadata) = metadata_range(&program.bytecode) {
+        text += &format!(
+            "\n; ---- metadata {:#06x} ({} bytes)\n{:#06x}  {}\n",
+            metadata.start,
+            metadata.len(),
+            metadata.start,
+            to_hex_string(&program.bytecode[metadata.clone()])
+        );
+    }
+    text
+}
+
+/// Renders the disassembly listing as JSON.
+pub fn listing_json(program: &Program, sourcemap: Option<&SourceMap>, constants: bool) -> Value {
+    let mut blocks: Vec<Value> = vec![];
+    let mut instructions: Vec<Value> = vec![];
+    let mut block_start = None;
+    let mut finish_block = |start: Option<usize>, instructions: &mut Vec<Value>| {
+        if let Some(start) = start {
+            blocks.push(json!({
+                "start": start,
+                "instructions": std::mem::take(instructions),
+            }));
+        }
+    };
+    for line in listing_lines(program, sourcemap, constants) {
+        if line.block_start {
+            finish_block(block_start, &mut instructions);
+            block_start = Some(line.pc);
+        }
+        let mut inst = json!({
+            "pc": line.pc,
+            "bytes": to_hex_string(line.bytes),
+            "mnemonic": line.mnemonic,
+            "jumpdest": line.jumpdest,
+        });
+        if let Some(data) = line.push_data {
+            inst["push_data"] = json!(format!("0x{}", to_hex_string(data)));
+        }
+        if constants {
+            inst["operands"] = json!(line
+                .operands
+                .iter()
+                .map(|o| o.map(|v| format!("{:#x}", v)))
+                .collect::<Vec<_>>());
+            inst["value"] = json!(line.value.map(|v| format!("{:#x}", v)));
+        }
+        if let Some((location, snippet)) = line.source {
+            inst["source"] = json!({ "location": location, "snippet": snippet });
+        }
+        instructions.push(inst);
+    }
+    finish_block(block_start, &mut instructions);
+
+    let metadata = metadata_range(&program.bytecode).map(|r| {
+        json!({
+            "start": r.start,
+            "length": r.len(),
+            "data": to_hex_string(&program.bytecode[r]),
+        })
+    });
+    json!({
+        "length": program.bytecode.len(),
+        "blocks": blocks,
+        "metadata": metadata,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // PUSH1 4 PUSH1 2 ADD JUMP JUMPDEST STOP + metadata (a1 65 "bzzr0" 00 .. length)
+    const BYTECODE: &str = "0x6004600201565b00a165627a7a7230000008";
+
+    #[test]
+    fn metadata() {
+        let bytecode = hexutil::read_hex(BYTECODE).unwrap();
+        assert_eq!(metadata_range(&bytecode), Some(8..bytecode.len()));
+        assert_eq!(metadata_range(&[0x60, 0x00]), None);
+        assert_eq!(metadata_range(&[0x00, 0x00, 0x01]), None);
+    }
+
+    #[test]
+    fn listings() {
+        let bytecode = hexutil::read_hex(BYTECODE).unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+
+        let text = listing_text(&program, None, true);
+        assert!(text.contains("; ---- basic block 0x0000\n"), "{}", text);
+        assert!(
+            text.contains("; ---- basic block 0x0006 (jump target)"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("0x0004  01             ADD") && text.contains("args: 0x2, 0x4 | = 0x6"),
+            "{}",
+            text
+        );
+        assert!(
+            text.contains("; ---- metadata 0x0008 (10 bytes)"),
+            "{}",
+            text
+        );
+        assert!(!text.contains("LOG2"), "{}", text);
+
+        let json = listing_json(&program, None, true);
+        assert_eq!(json["blocks"].as_array().unwrap().len(), 2);
+        assert_eq!(json["blocks"][0]["instructions"][0]["push_data"], "0x04");
+        assert_eq!(json["blocks"][0]["instructions"][2]["value"], "0x6");
+        assert_eq!(json["blocks"][1]["instructions"][0]["jumpdest"], true);
+        assert_eq!(json["metadata"]["start"], 8);
+
+        let json = listing_json(&program, None, false);
+        assert!(json["blocks"][0]["instructions"][2].get("value").is_none());
+    }
+}
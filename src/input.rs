@@ -0,0 +1,121 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::asm::assemble;
+use anyhow::Context;
+use std::io::Read;
+
+/// Removes all whitespace (e.g., line breaks of wrapped hex dumps) and the `0x` prefix from hex
+/// encoded bytecode.
+pub fn normalize_hex(s: &str) -> String {
+    let s: String = s.chars().filter(|c| !c.is_whitespace()).collect();
+    if let Some(stripped) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        stripped.to_string()
+    } else {
+        s
+    }
+}
+
+/// Decodes hex encoded bytecode. In contrast to `hexutil::read_hex`, which silently pads odd
+/// length input, we reject anything that is not a whole number of bytes.
+pub fn to_hex(s: &str) -> anyhow::Result<Vec<u8>> {
+    let s = normalize_hex(s);
+    if let Some((pos, c)) = s.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        bail!(
+            "invalid character {:?} at offset {} of hex encoded bytecode",
+            c,
+            pos
+        );
+    }
+    if !s.len().is_multiple_of(2) {
+        bail!(
+            "hex encoded bytecode has an odd number of digits ({}) - truncated input?",
+            s.len()
+        );
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| Ok(u8::from_str_radix(&s[i..i + 2], 16)?))
+        .collect()
+}
+
+/// Reads the given file or stdin if the path is `-`.
+pub fn read_input(path: &str) -> anyhow::Result<Vec<u8>> {
+    if path == "-" {
+        let mut data = vec![];
+        std::io::stdin()
+            .read_to_end(&mut data)
+            .context("failed to read from stdin")?;
+        Ok(data)
+    } else {
+        std::fs::read(path).with_context(|| format!("failed to read {}", path))
+    }
+}
+
+/// Whether the data is raw binary bytecode rather than hex encoded text. Hex encoded bytecode
+/// (including library placeholders) consists only of printable ASCII characters and whitespace.
+fn is_binary(data: &[u8]) -> bool {
+    data.iter()
+        .any(|b| !(b.is_ascii_graphic() || b.is_ascii_whitespace()))
+}
+
+/// Reads bytecode from the given file (or stdin for `-`), which is either hex encoded (with or
+/// without `0x` prefix and whitespace), raw binary or EVM assembly (`.evm` files). Returns the
+/// normalized hex string, such that library placeholders can still be linked.
+pub fn read_bytecode(path: &str) -> anyhow::Result<String> {
+    let data = read_input(path)?;
+    if path.ends_with(".evm") {
+        let bytecode = assemble(&String::from_utf8(data)?)
+            .with_context(|| format!("failed to assemble {}", path))?;
+        Ok(bytecode.iter().map(|b| format!("{:02x}", b)).collect())
+    } else if is_binary(&data) {
+        Ok(data.iter().map(|b| format!("{:02x}", b)).collect())
+    } else {
+        Ok(normalize_hex(&String::from_utf8(data)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_normalization() {
+        assert_eq!(to_hex("0x6001").unwrap(), vec![0x60, 0x01]);
+        assert_eq!(to_hex("60 01\n5B\r\n").unwrap(), vec![0x60, 0x01, 0x5b]);
+        assert_eq!(to_hex("0X6001").unwrap(), vec![0x60, 0x01]);
+        assert!(to_hex("").unwrap().is_empty());
+
+        let err = to_hex("0x600").unwrap_err().to_string();
+        assert!(err.contains("odd number of digits (3)"), "{}", err);
+        let err = to_hex("60__").unwrap_err().to_string();
+        assert!(err.contains("'_' at offset 2"), "{}", err);
+    }
+
+    #[test]
+    fn binary_detection() {
+        assert!(!is_binary(b"0x6080604052\n"));
+        assert!(!is_binary(b"73__lib.sol:Math______________________3014"));
+        assert!(is_binary(&[0x60, 0x80, 0x60, 0x40, 0x52]));
+
+        let dir = crate::testutil::TestDir::new("binary_detection");
+        let path = dir.join("code.bin");
+        std::fs::write(&path, [0x60, 0x01, 0x00]).unwrap();
+        assert_eq!(read_bytecode(path.to_str().unwrap()).unwrap(), "600100");
+        std::fs::write(&path, "0x6001\n00\n").unwrap();
+        assert_eq!(read_bytecode(path.to_str().unwrap()).unwrap(), "600100");
+    }
+}
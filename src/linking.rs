@@ -0,0 +1,362 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::abi::parse_uint;
+use crate::combinedjson::{ImmutableReferences, LinkReferences};
+use ethereum_types::U256;
+use std::collections::{BTreeMap, BTreeSet};
+use tiny_keccak::{Hasher, Keccak};
+
+/// Placeholders for library addresses take the place of the 20 byte address in the hex encoded
+/// bytecode.
+const PLACEHOLDER_LEN: usize = 40;
+
+/// Library addresses given as `Name=0xaddress`, where the name is either the fully qualified name
+/// (`file.sol:Name`) or only the name of the library.
+#[derive(Clone, Debug, Default)]
+pub struct Libraries {
+    addresses: BTreeMap<String, String>,
+}
+
+impl Libraries {
+    /// Adds a library given as `Name=0xaddress`.
+    pub fn add(&mut self, link: &str) -> anyhow::Result<()> {
+        let (name, address) = link
+            .rsplit_once('=')
+            .ok_or_else(|| anyhow!("invalid library {:?}, expected Name=0xaddress", link))?;
+        let address = address.trim();
+        let address = address.strip_prefix("0x").unwrap_or(address);
+        if address.len() != PLACEHOLDER_LEN || !address.chars().all(|c| c.is_ascii_hexdigit()) {
+            bail!("invalid address for library {}: {:?}", name, address);
+        }
+        self.addresses
+            .insert(name.trim().to_string(), address.to_ascii_lowercase());
+        Ok(())
+    }
+
+    /// Looks up the address of the library by its fully qualified name, falling back to only the
+    /// name of the library.
+    fn get(&self, fully_qualified_name: &str) -> Option<&str> {
+        self.addresses
+            .get(fully_qualified_name)
+            .or_else(|| {
+                let name = fully_qualified_name.rsplit(':').next().unwrap();
+                self.addresses.get(name)
+            })
+            .map(|s| s.as_str())
+    }
+}
+
+/// The placeholder of solc >= 0.5, which is derived from the keccak256 hash of the fully
+/// qualified library name.
+fn hash_placeholder(fully_qualified_name: &str) -> String {
+    let mut hasher = Keccak::v256();
+    let mut hash = [0u8; 32];
+    hasher.update(fully_qualified_name.as_bytes());
+    hasher.finalize(&mut hash);
+    let hex: String = hash[..17].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("__${}$__", hex)
+}
+
+/// The placeholder of solc < 0.5, which contains the library name truncated to 36 characters and
+/// padded with `_`.
+fn name_placeholder(fully_qualified_name: &str) -> String {
+    let name: String = fully_qualified_name.chars().take(36).collect();
+    format!("__{:_<width$}", name, width = PLACEHOLDER_LEN - 2)
+}
+
+/// Returns the offsets of all placeholders in the hex encoded bytecode.
+fn find_placeholders(bytecode: &str) -> Vec<usize> {
+    let mut offsets = vec![];
+    let mut i = 0;
+    while let Some(pos) = bytecode[i..].find("__") {
+        let start = i + pos;
+        if start + PLACEHOLDER_LEN > bytecode.len() {
+            break;
+        }
+        offsets.push(start);
+        i = start + PLACEHOLDER_LEN;
+    }
+    offsets
+}
+
+/// Substitutes the library placeholders in the hex encoded bytecode with the addresses of the
+/// libraries. The positions of the placeholders are taken from the link references of JSON inputs
+/// (if available) and otherwise the placeholders are recognized by the library names. Fails with a
+/// list of all libraries without an address if any placeholder remains.
+pub fn link_bytecode(
+    bytecode: &str,
+    libraries: &Libraries,
+    link_references: &LinkReferences,
+) -> anyhow::Result<String> {
+    let prefix = if bytecode.starts_with("0x") { 2 } else { 0 };
+    let mut linked = bytecode.to_string();
+    let mut unresolved = BTreeSet::new();
+
+    // we know the exact positions of the placeholders from the link references
+    let mut known_names = BTreeSet::new();
+    for (file, file_libraries) in link_references.iter() {
+        for (name, references) in file_libraries.iter() {
+            let fully_qualified_name = format!("{}:{}", file, name);
+            let address = libraries.get(&fully_qualified_name);
+            for reference in references.iter() {
+                let start = prefix + 2 * reference.start;
+                let end = start + 2 * reference.length;
+                if reference.length != PLACEHOLDER_LEN / 2 || end > linked.len() {
+                    bail!(
+                        "invalid link reference for library {} at offset {}",
+                        fully_qualified_name,
+                        reference.start
+                    );
+                }
+                if let Some(address) = address {
+                    linked.replace_range(start..end, address);
+                } else {
+                    unresolved.insert(fully_qualified_name.clone());
+                }
+            }
+            known_names.insert(fully_qualified_name);
+        }
+    }
+
+    // otherwise we recognize the placeholders by the names of the libraries
+    let mut placeholders: BTreeMap<String, String> = BTreeMap::new();
+    for name in known_names.iter().chain(libraries.addresses.keys()) {
+        placeholders.insert(hash_placeholder(name), name.clone());
+        placeholders.insert(name_placeholder(name), name.clone());
+    }
+    for start in find_placeholders(&linked) {
+        let placeholder = linked[start..start + PLACEHOLDER_LEN].to_string();
+        let name = if let Some(name) = placeholders.get(&placeholder) {
+            name.clone()
+        } else if placeholder.starts_with("__$") && placeholder.ends_with("$__") {
+            // we cannot recover the name from the hash
+            placeholder.clone()
+        } else {
+            placeholder.trim_matches('_').to_string()
+        };
+        if let Some(address) = libraries.get(&name) {
+            linked.replace_range(start..start + PLACEHOLDER_LEN, address);
+        } else {
+            unresolved.insert(name);
+        }
+    }
+
+    if !unresolved.is_empty() {
+        let unresolved: Vec<String> = unresolved.into_iter().collect();
+        bail!(
+            "bytecode contains unlinked libraries: {} (use --link Name=0xaddress)",
+            unresolved.join(", ")
+        );
+    }
+    Ok(linked)
+}
+
+/// Values of immutable variables given as `id=value`, where `id` is the AST id of the variable (as
+/// in the `immutableReferences` of the compiler output).
+#[derive(Clone, Debug, Default)]
+pub struct Immutables {
+    values: BTreeMap<String, U256>,
+}
+
+impl Immutables {
+    /// Adds the value of an immutable given as `id=value`. The value is either a decimal or a `0x`
+    /// prefixed hex integer (e.g., an address).
+    pub fn add(&mut self, immutable: &str) -> anyhow::Result<()> {
+        let (id, value) = immutable
+            .split_once('=')
+            .ok_or_else(|| anyhow!("invalid immutable {:?}, expected id=value", immutable))?;
+        let value = parse_uint(value.trim())
+            .map_err(|e| anyhow!("invalid value for immutable {}: {}", id, e))?;
+        self.values.insert(id.trim().to_string(), value);
+        Ok(())
+    }
+}
+
+/// Fills the placeholders of immutable variables in the hex encoded runtime code, which are only
+/// zeros in the compiler output. Returns the linked bytecode and the ids of the immutables without
+/// a value, whose placeholders are left as zeros.
+pub fn link_immutables(
+    bytecode: &str,
+    immutables: &Immutables,
+    immutable_references: &ImmutableReferences,
+) -> anyhow::Result<(String, Vec<String>)> {
+    let prefix = if bytecode.starts_with("0x") { 2 } else { 0 };
+    let mut linked = bytecode.to_string();
+    let mut unresolved = vec![];
+
+    for (id, references) in immutable_references.iter() {
+        let value = if let Some(value) = immutables.values.get(id) {
+            value
+        } else {
+            unresolved.push(id.clone());
+            continue;
+        };
+        let mut bytes = [0u8; 32];
+        value.to_big_endian(&mut bytes);
+        let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+        for reference in references.iter() {
+            let start = prefix + 2 * reference.start;
+            let end = start + 2 * reference.length;
+            if reference.length != 32 || end > linked.len() {
+                bail!(
+                    "invalid reference for immutable {} at offset {}",
+                    id,
+                    reference.start
+                );
+            }
+            linked.replace_range(start..end, &hex);
+        }
+    }
+    Ok((linked, unresolved))
+}
+
+/// Returns the byte ranges of the placeholders of all immutable variables in the runtime code.
+pub fn immutable_ranges(immutable_references: &ImmutableReferences) -> Vec<std::ops::Range<usize>> {
+    immutable_references
+        .values()
+        .flatten()
+        .map(|reference| reference.start..(reference.start + reference.length))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::combinedjson::LinkReference;
+
+    const ADDRESS: &str = "00000000000000000000000000000000000000aa";
+
+    fn libraries(links: &[&str]) -> Libraries {
+        let mut libraries = Libraries::default();
+        for l in links.iter() {
+            libraries.add(l).unwrap();
+        }
+        libraries
+    }
+
+    #[test]
+    fn hash_placeholders() {
+        // placeholder as emitted by solc for `contracts/lib.sol:L`
+        let placeholder = hash_placeholder("contracts/lib.sol:L");
+        assert_eq!(placeholder.len(), PLACEHOLDER_LEN);
+        let bytecode = format!("0x73{}3014", placeholder);
+
+        let linked = link_bytecode(
+            &bytecode,
+            &libraries(&["contracts/lib.sol:L=0x00000000000000000000000000000000000000AA"]),
+            &LinkReferences::new(),
+        )
+        .unwrap();
+        assert_eq!(linked, format!("0x73{}3014", ADDRESS));
+
+        // with the link references we also know the name of the library
+        let mut link_references = LinkReferences::new();
+        link_references
+            .entry("contracts/lib.sol".to_string())
+            .or_default()
+            .insert(
+                "L".to_string(),
+                vec![LinkReference {
+                    start: 1,
+                    length: 20,
+                }],
+            );
+        let linked = link_bytecode(
+            &bytecode,
+            &libraries(&[&format!("L={}", ADDRESS)]),
+            &link_references,
+        )
+        .unwrap();
+        assert_eq!(linked, format!("0x73{}3014", ADDRESS));
+
+        let err = link_bytecode(&bytecode, &Libraries::default(), &link_references)
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("contracts/lib.sol:L"), "{}", err);
+    }
+
+    #[test]
+    fn name_placeholders() {
+        let bytecode = format!("73{}3014", name_placeholder("lib.sol:Math"));
+        assert!(bytecode.starts_with("73__lib.sol:Math____"));
+
+        let linked = link_bytecode(
+            &bytecode,
+            &libraries(&[&format!("Math={}", ADDRESS)]),
+            &LinkReferences::new(),
+        )
+        .unwrap();
+        assert_eq!(linked, format!("73{}3014", ADDRESS));
+
+        let err = link_bytecode(&bytecode, &Libraries::default(), &LinkReferences::new())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("lib.sol:Math"), "{}", err);
+
+        assert!(Libraries::default().add("Math=0x1234").is_err());
+        assert!(Libraries::default().add("Math").is_err());
+
+        // solc < 0.5 keeps at most 36 characters of the name, even if 38 would fit
+        let name = "contracts/libraries/SafeMath.sol:SafeMathLib";
+        let placeholder = name_placeholder(name);
+        assert_eq!(placeholder, "__contracts/libraries/SafeMath.sol:Saf__");
+        let bytecode = format!("73{}3014", placeholder);
+        let linked = link_bytecode(
+            &bytecode,
+            &libraries(&[&format!("{}={}", name, ADDRESS)]),
+            &LinkReferences::new(),
+        )
+        .unwrap();
+        assert_eq!(linked, format!("73{}3014", ADDRESS));
+    }
+
+    #[test]
+    fn immutables() {
+        // PUSH32 <immutable 7> PUSH32 <immutable 7> ADD
+        let zeros = "00".repeat(32);
+        let bytecode = format!("7f{}7f{}01", zeros, zeros);
+        let mut immutable_references = ImmutableReferences::new();
+        immutable_references.insert(
+            "7".to_string(),
+            vec![
+                LinkReference {
+                    start: 1,
+                    length: 32,
+                },
+                LinkReference {
+                    start: 34,
+                    length: 32,
+                },
+            ],
+        );
+
+        let mut immutables = Immutables::default();
+        immutables.add("7=0x1234").unwrap();
+        let (linked, unresolved) =
+            link_immutables(&bytecode, &immutables, &immutable_references).unwrap();
+        let value = format!("{}1234", "00".repeat(30));
+        assert_eq!(linked, format!("7f{}7f{}01", value, value));
+        assert!(unresolved.is_empty());
+
+        let (linked, unresolved) =
+            link_immutables(&bytecode, &Immutables::default(), &immutable_references).unwrap();
+        assert_eq!(linked, bytecode);
+        assert_eq!(unresolved, vec!["7".to_string()]);
+
+        assert!(Immutables::default().add("7=foo").is_err());
+    }
+}
@@ -20,105 +20,882 @@ extern crate lazy_static;
 extern crate anyhow;
 
 use anyhow::Context;
-use clap::{arg, Command};
+use clap::{arg, ArgGroup, ArgMatches, Command};
+use ethereum_types::U256;
+use std::ffi::OsString;
 use std::fs::File;
 use std::io::Write;
 use std::path::{Path, PathBuf};
 
+mod abi;
+mod alloc;
 mod analysis;
+mod asm;
+mod cfg;
 mod codegen;
 mod combinedjson;
+mod coverage;
+mod disasm;
+mod input;
 #[allow(dead_code)]
 mod instructions;
+mod linking;
+mod output;
+mod parallel;
 mod sourcemap;
+mod stats;
+#[cfg(test)]
+mod testutil;
+mod vyper;
 
-use codegen::translate_to_c;
-use combinedjson::{read_combined_from_file, read_single_contract_combined_from_file};
-use sourcemap::parse_source_map;
+use abi::encode_constructor_args;
+use alloc::{normalize_address, read_alloc_file};
+use analysis::Program;
+use cfg::{build_cfg, cfg_dot, cfg_json};
+use codegen::{dictionary_entries, program_constants, translate_to_c, CodegenOptions};
+use combinedjson::{
+    detect_json_format_of_file, read_contracts_from_file, read_foundry_out_dir,
+    read_single_contract_combined_from_file, Combined, Contract, GeneratedSource, JsonFormat,
+    LinkReferences,
+};
+use coverage::{compute_coverage, read_block_list, read_hits, write_cobertura, write_lcov};
+use disasm::{listing_json, listing_text};
+use input::{read_bytecode, to_hex};
+use linking::{immutable_ranges, link_bytecode, link_immutables, Immutables, Libraries};
+use output::OutputLayout;
+use parallel::{default_jobs, map_parallel};
+use sourcemap::{parse_source_map, parse_source_map_from_contents, SourceMap};
+use stats::{compute_stats, stats_json, stats_text};
 
-//impl std::error::Error for hexutil::ParseHexError {}
+/// Returns the library addresses given with `--link Name=0xaddress`.
+fn libraries(matches: &ArgMatches) -> anyhow::Result<Libraries> {
+    let mut libraries = Libraries::default();
+    if let Some(links) = matches.values_of("link") {
+        for link in links {
+            libraries.add(link)?;
+        }
+    }
+    Ok(libraries)
+}
 
-fn to_hex(b: &str) -> anyhow::Result<Vec<u8>> {
-    match hexutil::read_hex(b) {
-        Ok(b) => anyhow::Result::Ok(b),
-        Err(e) => anyhow::Result::Err(anyhow!("Failed to convert hex bytes (Error: {:?})", e)),
+/// Returns the values of immutable variables given with `--immutable id=value`.
+fn immutables(matches: &ArgMatches) -> anyhow::Result<Immutables> {
+    let mut immutables = Immutables::default();
+    if let Some(values) = matches.values_of("immutable") {
+        for value in values {
+            immutables.add(value)?;
+        }
+    }
+    Ok(immutables)
+}
+
+/// Returns the runtime code of the contract with the addresses of the libraries and the values of
+/// the immutable variables filled in.
+fn runtime_code(
+    name: &str,
+    contract: &Contract,
+    libraries: &Libraries,
+    immutables: &Immutables,
+) -> anyhow::Result<Vec<u8>> {
+    let bytecode = link_bytecode(
+        contract.bin_runtime.trim(),
+        libraries,
+        &contract.deployed_link_references,
+    )?;
+    let (bytecode, unresolved) =
+        link_immutables(&bytecode, immutables, &contract.immutable_references)?;
+    if !unresolved.is_empty() {
+        println!(
+            "[WARNING] no value for the immutable variable(s) {} of contract {} (use --immutable id=value); their placeholders are translated as constant zero!",
+            unresolved.join(", "),
+            name
+        );
+    }
+    to_hex(&bytecode)
+}
+
+/// Returns the constructor arguments given on the command line, either already ABI encoded as hex
+/// string or as JSON array, which is encoded according to the constructor in the ABI definition.
+fn constructor_args(matches: &ArgMatches, abi: Option<&str>) -> anyhow::Result<Vec<u8>> {
+    if let Some(args) = matches.value_of("constructor-args") {
+        to_hex(args.trim()).context("failed to convert constructor arguments from hex")
+    } else if let Some(args) = matches.value_of("constructor-args-json") {
+        let abi = abi.ok_or(anyhow!(
+            "--constructor-args-json requires the ABI definition of the contract"
+        ))?;
+        encode_constructor_args(abi, args)
+    } else {
+        Ok(vec![])
     }
 }
 
-fn write_abi(name: &str, evm_path: &Path, abi: &[u8]) -> anyhow::Result<()> {
-    let abi_file = format!("fuzz/abi/{}.abi", name);
-    let file_path = evm_path.join(abi_file);
-    println!("Writing ABI to {}", file_path.display());
+fn write_abi(name: &str, output: &OutputLayout, abi: &[u8]) -> anyhow::Result<PathBuf> {
+    let file_path = output.abi(name);
     let mut file = File::create(&file_path)?;
     file.write_all(abi)?;
+    output.record(name, &[("abi", &file_path)])?;
+    Ok(file_path)
+}
+
+/// Parses a sourcemap of a contract from a combined.json file. The source files are taken from the
+/// input if they are embedded (e.g., in build-info files) or are otherwise resolved relative to the
+/// location of the combined.json file.
+fn load_sourcemap(
+    combined_path: &Path,
+    combined: &Combined,
+    srcmap: &str,
+    generated_sources: &[GeneratedSource],
+) -> anyhow::Result<SourceMap> {
+    let embedded_sources: Option<Vec<(String, String)>> = combined
+        .source_list
+        .iter()
+        .map(|s| {
+            combined
+                .source_contents
+                .get(s)
+                .map(|c| (s.clone(), c.clone()))
+        })
+        .collect();
+    if let Some(sources) = embedded_sources.filter(|s| !s.is_empty()) {
+        parse_source_map_from_contents(srcmap, &sources, generated_sources)
+    } else if let Some(parent) = combined_path.parent() {
+        let filepaths: Vec<PathBuf> = combined
+            .source_list
+            .iter()
+            .cloned()
+            .map(|s| parent.join(s))
+            .collect();
+        let files: Vec<&str> = filepaths.iter().map(|s| s.to_str().unwrap()).collect();
+        parse_source_map(srcmap, &files, generated_sources)
+    } else {
+        let files: Vec<&str> = combined.source_list.iter().map(|s| &**s).collect();
+        parse_source_map(srcmap, &files, generated_sources)
+    }
+    .with_context(|| {
+        format!(
+            "failed to parse sourcemap from combined.json at {:?}",
+            combined_path
+        )
+    })
+}
+
+/// Parses the sourcemap of the runtime code of a contract.
+fn load_runtime_sourcemap(
+    combined_path: &Path,
+    combined: &Combined,
+    contract: &Contract,
+) -> anyhow::Result<SourceMap> {
+    load_sourcemap(
+        combined_path,
+        combined,
+        &contract.srcmap_runtime,
+        &contract.generated_sources_runtime,
+    )
+}
+
+/// Parses the sourcemap of the init code (constructor) of a contract.
+fn load_constructor_sourcemap(
+    combined_path: &Path,
+    combined: &Combined,
+    contract: &Contract,
+) -> anyhow::Result<SourceMap> {
+    load_sourcemap(
+        combined_path,
+        combined,
+        &contract.srcmap,
+        &contract.generated_sources,
+    )
+}
+
+/// Determines the format of JSON inputs by looking at the file contents, unless it is forced on the
+/// command line; `None` if the input is not a JSON file.
+fn json_format(input: &str, forced: Option<JsonFormat>) -> anyhow::Result<Option<JsonFormat>> {
+    if forced.is_some() {
+        Ok(forced)
+    } else if input.ends_with(".json") {
+        Ok(Some(detect_json_format_of_file(input)?))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Whether a contract from a Foundry project should be translated when no contract was selected
+/// explicitly. We skip contracts without runtime code (interfaces, abstract contracts) and
+/// everything that belongs to tests, scripts or dependencies.
+fn is_foundry_project_contract(source_path: &str, contract: &Contract) -> bool {
+    let runtime = contract.bin_runtime.trim();
+    if runtime.is_empty() || runtime == "0x" {
+        return false;
+    }
+    let skipped_dirs = ["lib/", "test/", "script/"];
+    !(skipped_dirs.iter().any(|d| source_path.starts_with(d))
+        || source_path.ends_with(".t.sol")
+        || source_path.ends_with(".s.sol"))
+}
+
+/// `--foundry-out`: translate the contracts of a Foundry project
+fn translate_foundry_out(
+    matches: &ArgMatches,
+    output: &OutputLayout,
+    out_dir: &str,
+    selected: Option<&str>,
+    codegen_options: &CodegenOptions,
+) -> anyhow::Result<()> {
+    let combined = read_foundry_out_dir(Path::new(out_dir))?;
+    let libraries = libraries(matches)?;
+    let immutables = immutables(matches)?;
+    if matches.is_present("emit-sourcemap") {
+        println!("[WARNING] Cannot emit source(-map) information for Foundry artifacts, since they do not contain the source list! Use the build-info files instead (forge build --build-info).");
+    }
+
+    let mut identifiers = std::collections::BTreeSet::new();
+    for (key, contract) in combined.contracts.iter() {
+        let (source_path, name) = key.rsplit_once(':').unwrap();
+        if let Some(selected) = selected {
+            if selected != name && selected != key {
+                continue;
+            }
+        } else if !is_foundry_project_contract(source_path, contract) {
+            continue;
+        }
+        if !identifiers.insert(name.to_string()) {
+            println!(
+                "[WARNING] skipping {} - a contract named {} was already translated",
+                key, name
+            );
+            continue;
+        }
+
+        let bytecode = runtime_code(key, contract, &libraries, &immutables)
+            .with_context(|| format!("failed to convert bytecode of contract {} from hex", key))?;
+        let constructor_bytecode =
+            link_bytecode(contract.bin.trim(), &libraries, &contract.link_references)
+                .and_then(|b| to_hex(&b))
+                .with_context(|| {
+                    format!(
+                        "failed to convert constructor bytecode of contract {} from hex",
+                        key
+                    )
+                })?;
+        let abi_path = write_abi(name, output, contract.abi.as_bytes())?;
+        println!("Writing ABI to {}", abi_path.display());
+        let constructor_args = constructor_args(matches, Some(&contract.abi))?;
+
+        println!("Translating contract {} ({}) to C++...", name, source_path);
+        let warnings = translate_to_c(
+            output,
+            name,
+            bytecode,
+            Some(constructor_bytecode),
+            &immutable_ranges(&contract.immutable_references),
+            &constructor_args,
+            &[],
+            None,
+            None,
+            codegen_options,
+        )?;
+        warnings.iter().for_each(|w| println!("{}", w));
+    }
+
+    if identifiers.is_empty() {
+        bail!("no contracts to translate found in {}", out_dir);
+    }
     Ok(())
 }
 
-fn main() -> anyhow::Result<()> {
-    let app = Command::new(env!("CARGO_BIN_NAME"))
-        .about("EVM bytecode to C++ transpiler targeting the eEVM framework")
-        .version(env!("CARGO_PKG_VERSION"))
-        .arg(arg!(-a --abi [ABI_FILE] "path to abi definition file").multiple_values(false).multiple_occurrences(false))
-        .arg(arg!(-A --"translate-all" "Translate all contracts found in combined.json"))
-        .arg(arg!(-c --"combined-json" "force use of combined json as input (auto-detected on filetype)"))
-        .arg(arg!(-C --"single-combined-json" "force use of combined json of a single contract (i.e., truffle-style)"))
-        .arg(arg!(-e --"evm-path" [EVM_PATH] "path to eEVM project").default_value("./eEVM").multiple_values(false).multiple_occurrences(false))
-        .arg(arg!(-s --"emit-sourcemap" "emit source information to generated code for easier codegen debugging"))
-        .arg(arg!(-F --"clang-format" "launch clang-format on generated code"))
-        .arg(arg!(--"contract-name" [NAME] "contract name to look for in the combined.json input format (defaults to the <name> parameter)").multiple_values(false).multiple_occurrences(false))
-        .arg(arg!(<name> "name/identifier of the contract for the generated code"))
-        .arg(arg!([input] "path to EVM runtime code (.bin-runtime) or combined-json input"))
-        .arg(arg!([constructor_path] "path to EVM constructor code (.bin)"));
-    let matches = app.get_matches();
-
-    let evm_path = if let Some(path) = matches.value_of("evm-path") {
-        let p = Path::new(path);
-        if p.exists() {
-            Ok(p)
+/// `--alloc`: translate the accounts with code of a state dump, including their storage
+fn translate_alloc(
+    output: &OutputLayout,
+    alloc_path: &str,
+    address: Option<&str>,
+    codegen_options: &CodegenOptions,
+) -> anyhow::Result<()> {
+    let accounts = read_alloc_file(alloc_path)?;
+    let selected = address.map(normalize_address).transpose()?;
+    if let Some(selected) = selected.as_ref() {
+        if !accounts.contains_key(selected) {
+            bail!(
+                "no account with code at address 0x{} in {}",
+                selected,
+                alloc_path
+            );
+        }
+    }
+
+    for (address, account) in accounts.iter() {
+        if selected.as_ref().is_some_and(|s| s != address) {
+            continue;
+        }
+        let name = format!("account_{}", address);
+        let bytecode = to_hex(&account.code)
+            .with_context(|| format!("failed to convert code of account 0x{} from hex", address))?;
+        let storage: Vec<(U256, U256)> = account.storage.iter().map(|(k, v)| (*k, *v)).collect();
+
+        println!(
+            "Translating account 0x{} ({} storage slots) to C++ as {}...",
+            address,
+            storage.len(),
+            name
+        );
+        let warnings = translate_to_c(
+            output,
+            &name,
+            bytecode,
+            None,
+            &[],
+            &[],
+            &storage,
+            None,
+            None,
+            codegen_options,
+        )?;
+        warnings.iter().for_each(|w| println!("{}", w));
+    }
+
+    if accounts.is_empty() {
+        bail!("no accounts with code found in {}", alloc_path);
+    }
+    Ok(())
+}
+
+/// Selects the contract given with `--contract-name` (fully qualified or only the name) or the only
+/// contract of the compiler output.
+fn select_contract<'a>(
+    combined: &'a Combined,
+    input: &str,
+    contract_name: Option<&str>,
+) -> anyhow::Result<(&'a String, &'a Contract)> {
+    let candidates: Vec<(&String, &Contract)> = combined
+        .contracts
+        .iter()
+        .filter(|(name, _)| {
+            if let Some(look_for) = contract_name {
+                *name == look_for || name.rsplit(':').next() == Some(look_for)
+            } else {
+                true
+            }
+        })
+        .collect();
+    match candidates.as_slice() {
+        [c] => Ok(*c),
+        _ => {
+            let available: Vec<&str> = combined.contracts.keys().map(|s| &**s).collect();
+            bail!(
+                "failed to select a single contract from {} (use --contract-name); available contracts: {}",
+                input,
+                available.join(", ")
+            );
+        }
+    }
+}
+
+/// `evm2cpp coverage`: attribute basic block coverage to solidity source lines
+fn coverage_main(matches: &ArgMatches) -> anyhow::Result<()> {
+    let input = matches.value_of("input").unwrap();
+    let combined_path = Path::new(input);
+    let format = json_format(input, None)?.unwrap_or(JsonFormat::Combined);
+    let combined = read_contracts_from_file(input, format)?;
+
+    let (name, contract) = select_contract(&combined, input, matches.value_of("contract-name"))?;
+    println!("Computing coverage for contract {}", name);
+
+    let bytecode = link_bytecode(
+        contract.bin_runtime.trim(),
+        &libraries(matches)?,
+        &contract.deployed_link_references,
+    )
+    .and_then(|b| to_hex(&b))
+    .with_context(|| {
+        format!(
+            "failed to convert bytecode of contract {} in combined.json from hex",
+            name
+        )
+    })?;
+    let mut program = Program::new(&bytecode);
+    program.optimize();
+
+    let sourcemap = load_runtime_sourcemap(combined_path, &combined, contract)?;
+    let blocks = read_block_list(matches.value_of("bb-list").unwrap())?;
+    let hits = read_hits(matches.value_of("hits").unwrap())?;
+
+    let coverage = compute_coverage(&program, &blocks, &hits, &sourcemap);
+
+    let output = matches.value_of("output").unwrap();
+    let mut file = File::create(output)
+        .with_context(|| format!("failed to create coverage report at {}", output))?;
+    match matches.value_of("format").unwrap() {
+        "lcov" => write_lcov(&mut file, name, &coverage)?,
+        "cobertura" => write_cobertura(&mut file, name, &coverage)?,
+        f => bail!("unsupported coverage format {}", f),
+    }
+    println!("Wrote coverage report to {}", output);
+    Ok(())
+}
+
+/// Reads the runtime code of a contract to analyze, which is given as bytecode (hex, raw binary or
+/// EVM assembly) or compiler output, and the sourcemap of the contract if requested.
+fn read_runtime_code(
+    matches: &ArgMatches,
+    with_sourcemap: bool,
+) -> anyhow::Result<(Vec<u8>, Option<SourceMap>)> {
+    let input = matches.value_of("input").unwrap();
+    let libraries = libraries(matches)?;
+
+    match json_format(input, None)? {
+        Some(JsonFormat::SingleContract) => {
+            if with_sourcemap {
+                bail!("source annotations require a combined-json or standard-JSON input");
+            }
+            let contract = read_single_contract_combined_from_file(input)?;
+            let bytecode = link_bytecode(
+                contract.bin_runtime.trim(),
+                &libraries,
+                &contract.deployed_link_references,
+            )
+            .and_then(|b| to_hex(&b))
+            .with_context(|| format!("failed to convert bytecode in {} from hex", input))?;
+            Ok((bytecode, None))
+        }
+        Some(format) => {
+            let combined = read_contracts_from_file(input, format)?;
+            let (name, contract) =
+                select_contract(&combined, input, matches.value_of("contract-name"))?;
+            let bytecode = link_bytecode(
+                contract.bin_runtime.trim(),
+                &libraries,
+                &contract.deployed_link_references,
+            )
+            .and_then(|b| to_hex(&b))
+            .with_context(|| format!("failed to convert bytecode of contract {} from hex", name))?;
+            let sourcemap = if with_sourcemap {
+                Some(load_runtime_sourcemap(
+                    Path::new(input),
+                    &combined,
+                    contract,
+                )?)
+            } else {
+                None
+            };
+            Ok((bytecode, sourcemap))
+        }
+        None => {
+            if with_sourcemap {
+                bail!("source annotations require a compiler output with sourcemap as input");
+            }
+            let bytecode =
+                link_bytecode(&read_bytecode(input)?, &libraries, &LinkReferences::new())
+                    .and_then(|b| to_hex(&b))?;
+            Ok((bytecode, None))
+        }
+    }
+}
+
+/// Writes the output of an analysis subcommand to the `--output` file or stdout.
+fn write_output(matches: &ArgMatches, output: &str) -> anyhow::Result<()> {
+    match matches.value_of("output") {
+        Some(path) => std::fs::write(path, output)
+            .with_context(|| format!("failed to write output to {}", path)),
+        None => {
+            print!("{}", output);
+            Ok(())
+        }
+    }
+}
+
+/// `evm2cpp disasm`: print a disassembly listing of the runtime code
+fn disasm_main(matches: &ArgMatches) -> anyhow::Result<()> {
+    let (bytecode, sourcemap) = read_runtime_code(matches, matches.is_present("source"))?;
+
+    let constants = matches.is_present("constants");
+    let mut program = Program::new(&bytecode);
+    if constants {
+        program.optimize();
+    }
+
+    let listing = match matches.value_of("format").unwrap() {
+        "json" => {
+            serde_json::to_string_pretty(&listing_json(&program, sourcemap.as_ref(), constants))?
+        }
+        _ => listing_text(&program, sourcemap.as_ref(), constants),
+    };
+    write_output(matches, &listing)
+}
+
+/// `evm2cpp cfg`: write the control flow graph of the runtime code
+fn cfg_main(matches: &ArgMatches) -> anyhow::Result<()> {
+    let (bytecode, _) = read_runtime_code(matches, false)?;
+    let mut program = Program::new(&bytecode);
+    program.optimize();
+    let cfg = build_cfg(&program);
+
+    let output = match matches.value_of("format").unwrap() {
+        "json" => serde_json::to_string_pretty(&cfg_json(&cfg))?,
+        _ => {
+            let input = matches.value_of("input").unwrap();
+            let name = matches.value_of("contract-name").unwrap_or_else(|| {
+                Path::new(input)
+                    .file_stem()
+                    .and_then(|s| s.to_str())
+                    .unwrap_or("cfg")
+            });
+            cfg_dot(&program, &cfg, name)
+        }
+    };
+    write_output(matches, &output)
+}
+
+/// `evm2cpp stats`: print statistics about the runtime code
+fn stats_main(matches: &ArgMatches) -> anyhow::Result<()> {
+    let (bytecode, _) = read_runtime_code(matches, false)?;
+    let mut program = Program::new(&bytecode);
+    program.optimize();
+    let stats = compute_stats(&program);
+
+    let output = match matches.value_of("format").unwrap() {
+        "json" => serde_json::to_string_pretty(&stats_json(&stats))?,
+        _ => stats_text(&stats),
+    };
+    write_output(matches, &output)
+}
+
+/// `evm2cpp dict`: write the fuzzing dictionary of the runtime code without translating it
+fn dict_main(matches: &ArgMatches) -> anyhow::Result<()> {
+    let (bytecode, _) = read_runtime_code(matches, false)?;
+    let mut program = Program::new(&bytecode);
+    program.optimize();
+
+    let entries = dictionary_entries(&program, program_constants(&program).iter());
+    write_output(matches, &entries.concat())
+}
+
+/// What to translate, as given on the command line.
+#[derive(Debug, PartialEq)]
+enum Translation<'a> {
+    /// a single contract (`name`) or all contracts (`None`) of bytecode or compiler output
+    Input {
+        input: &'a str,
+        name: Option<&'a str>,
+        constructor_path: Option<&'a str>,
+    },
+    /// the contracts of a Foundry project (all contracts in src/ or only the named one)
+    FoundryOut {
+        out_dir: &'a str,
+        name: Option<&'a str>,
+    },
+    /// the accounts with code of a state dump (all accounts or only the given address)
+    Alloc {
+        path: &'a str,
+        address: Option<&'a str>,
+    },
+}
+
+impl<'a> Translation<'a> {
+    /// `evm2cpp translate <name> <input> [constructor_path]`
+    fn from_translate(matches: &'a ArgMatches) -> Self {
+        let name = matches.value_of("name");
+        let input = matches.value_of("input").unwrap();
+        if matches.is_present("alloc") {
+            Translation::Alloc {
+                path: input,
+                address: name,
+            }
+        } else if Path::new(input).is_dir() {
+            Translation::FoundryOut {
+                out_dir: input,
+                name,
+            }
         } else {
-            Err(anyhow!(
-                "Provided path to eEVM does not exit! ({})",
-                p.display()
-            ))
+            Translation::Input {
+                input,
+                name,
+                constructor_path: matches.value_of("constructor_path"),
+            }
         }
-    } else {
-        let p = Path::new("./eEVM");
-        if p.exists() {
-            Ok(p)
+    }
+
+    /// `evm2cpp translate-all <input>`
+    fn from_translate_all(matches: &'a ArgMatches) -> Self {
+        let input = matches.value_of("input").unwrap();
+        if matches.is_present("alloc") {
+            Translation::Alloc {
+                path: input,
+                address: None,
+            }
+        } else if Path::new(input).is_dir() {
+            Translation::FoundryOut {
+                out_dir: input,
+                name: None,
+            }
+        } else {
+            Translation::Input {
+                input,
+                name: None,
+                constructor_path: None,
+            }
+        }
+    }
+
+    /// The invocation without subcommand, where the meaning of the positional arguments depends on
+    /// `--translate-all`, `--foundry-out` and `--alloc`.
+    fn from_legacy(matches: &'a ArgMatches) -> anyhow::Result<Self> {
+        let name = matches.value_of("name");
+        let input = matches.value_of("input");
+        if let Some(out_dir) = matches.value_of("foundry-out") {
+            if let Some(extra) = input {
+                bail!("unexpected argument {:?}: with --foundry-out only the contract name may be given (or use `translate-all {}`)", extra, out_dir);
+            }
+            return Ok(Translation::FoundryOut { out_dir, name });
+        }
+        if let Some(path) = matches.value_of("alloc") {
+            if let Some(extra) = input {
+                bail!("unexpected argument {:?}: with --alloc only the account address may be given (or use `translate-all --alloc {}`)", extra, path);
+            }
+            return Ok(Translation::Alloc {
+                path,
+                address: name,
+            });
+        }
+
+        if matches.is_present("translate-all") {
+            // the first positional argument is the compiler output
+            match (name, input) {
+                (Some(input), None) => Ok(Translation::Input {
+                    input,
+                    name: None,
+                    constructor_path: None,
+                }),
+                (Some(_), Some(extra)) => bail!(
+                    "unexpected argument {:?}: --translate-all takes only the compiler output (or use `translate-all <input>`)",
+                    extra
+                ),
+                (None, _) => bail!("missing compiler output: expected `--translate-all <input>`"),
+            }
         } else {
-            let p = Path::new("../eEVM");
+            match (name, input) {
+                (Some(name), Some(input)) => Ok(Translation::Input {
+                    input,
+                    name: Some(name),
+                    constructor_path: matches.value_of("constructor_path"),
+                }),
+                _ => bail!(
+                    "missing <input>: expected `<name> <input> [constructor_path]` (or use `translate-all <input>` to translate all contracts)"
+                ),
+            }
+        }
+    }
+}
+
+/// The eEVM project (`--evm-path`) or self-contained output directory (`--out-dir`) to write the
+/// generated files to.
+fn output_layout(matches: &ArgMatches) -> anyhow::Result<OutputLayout> {
+    let output = if let Some(out_dir) = matches.value_of("out-dir") {
+        OutputLayout::out_dir(Path::new(out_dir))?
+    } else {
+        let evm_path = if let Some(path) = matches.value_of("evm-path") {
+            let p = Path::new(path);
             if p.exists() {
                 Ok(p)
             } else {
                 Err(anyhow!(
-                    "failed to find eEVM project directory in './eEVM' or '../eEVM'"
+                    "Provided path to eEVM does not exit! ({})",
+                    p.display()
                 ))
             }
+        } else {
+            let p = Path::new("./eEVM");
+            if p.exists() {
+                Ok(p)
+            } else {
+                let p = Path::new("../eEVM");
+                if p.exists() {
+                    Ok(p)
+                } else {
+                    Err(anyhow!(
+                        "failed to find eEVM project directory in './eEVM' or '../eEVM'"
+                    ))
+                }
+            }
+        }?;
+        OutputLayout::eevm(evm_path)
+    };
+    Ok(output)
+}
+
+/// `evm2cpp remove`: delete the generated files of contracts and unregister them from contracts.h
+fn remove_main(matches: &ArgMatches) -> anyhow::Result<()> {
+    if let Some(out_dir) = matches.value_of("out-dir") {
+        if !Path::new(out_dir).is_dir() {
+            bail!("output directory {} does not exist", out_dir);
+        }
+    }
+    let output = output_layout(matches)?;
+    for name in matches.values_of("name").unwrap() {
+        for path in output.remove(name)? {
+            println!("Removed {}", path.display());
         }
-    }?;
+    }
+    Ok(())
+}
+
+/// Translates the contracts selected on the command line into the eEVM project.
+fn translate_main(matches: &ArgMatches, translation: Translation) -> anyhow::Result<()> {
+    let output = output_layout(matches)?;
 
-    if !if matches.is_present("translate-all") {
-        matches.is_present("name") && !matches.is_present("input")
+    let codegen_options = CodegenOptions {
+        compare_tracing: matches.value_of_t("trace-comparisons")?,
+        trace_state: matches.is_present("trace-state"),
+        trace_calls: matches.is_present("trace-calls"),
+        line_directives: matches.is_present("line-directives"),
+        translate_constructor: matches.is_present("translate-constructor"),
+        emit_asm: matches.is_present("emit-asm"),
+        build_fragment: matches
+            .value_of("build-fragment")
+            .map(|f| f.parse())
+            .transpose()?,
+        run_clang_format: matches.is_present("clang-format"),
+    };
+
+    if output.is_standalone() {
+        println!("Writing contracts to {}", output.root().display());
     } else {
-        matches.is_present("name") && matches.is_present("input")
-    } {
-        bail!("invalid arguments");
+        println!("Writing contracts to eEVM at {}", output.root().display());
     }
+    match translation {
+        Translation::FoundryOut { out_dir, name } => {
+            translate_foundry_out(matches, &output, out_dir, name, &codegen_options)?
+        }
+        Translation::Alloc { path, address } => {
+            translate_alloc(&output, path, address, &codegen_options)?
+        }
+        Translation::Input {
+            input,
+            name,
+            constructor_path,
+        } => translate_input(
+            matches,
+            &output,
+            input,
+            name,
+            constructor_path,
+            &codegen_options,
+        )?,
+    }
+    println!("Done!");
+    Ok(())
+}
+
+/// A contract of a compiler output together with the identifier used in the generated code.
+struct CombinedJob<'a> {
+    name: String,
+    identifier: String,
+    contract: &'a Contract,
+}
 
-    let input = if matches.is_present("translate-all") {
-        matches.value_of("name")
+/// Translates a contract of a compiler output. The messages are returned instead of printed, such
+/// that they can be printed in order when translating contracts in parallel.
+#[allow(clippy::too_many_arguments)]
+fn translate_combined_contract(
+    matches: &ArgMatches,
+    output: &OutputLayout,
+    combined_path: &Path,
+    combined: &Combined,
+    job: &CombinedJob,
+    libraries: &Libraries,
+    immutables: &Immutables,
+    codegen_options: &CodegenOptions,
+) -> anyhow::Result<Vec<String>> {
+    let (name, identifier, contract) = (&job.name, &job.identifier, job.contract);
+    let mut log = vec![];
+
+    let sourcemap = if matches.is_present("emit-sourcemap") {
+        Some(load_runtime_sourcemap(combined_path, combined, contract)?)
     } else {
-        matches.value_of("input")
-    }
-    .ok_or(anyhow!("Missing input"))?;
-    println!("reading input {}", input);
+        None
+    };
+    let constructor_sourcemap =
+        if matches.is_present("emit-sourcemap") && codegen_options.translate_constructor {
+            Some(load_constructor_sourcemap(
+                combined_path,
+                combined,
+                contract,
+            )?)
+        } else {
+            None
+        };
+
+    let bytecode = runtime_code(name, contract, libraries, immutables).with_context(|| {
+        format!(
+            "failed to convert bytecode of contract {} in combined.json from hex",
+            name
+        )
+    })?;
+
+    let constructor_bytecode = contract.bin.trim();
+    let constructor_bytecode =
+        link_bytecode(constructor_bytecode, libraries, &contract.link_references)
+            .and_then(|b| to_hex(&b))
+            .with_context(|| {
+                format!(
+            "failed to convert constructor bytecode of contract {} in combined.json from hex",
+            name
+        )
+            })?;
+
+    let abi_path = write_abi(identifier, output, contract.abi.as_bytes())?;
+    log.push(format!("Writing ABI to {}", abi_path.display()));
+    let constructor_args = constructor_args(matches, Some(&contract.abi))?;
+    log.push(format!(
+        "Translating contract with name {} (identifier {}) to C++...",
+        name, identifier
+    ));
+    let warnings = translate_to_c(
+        output,
+        identifier,
+        bytecode,
+        Some(constructor_bytecode),
+        &immutable_ranges(&contract.immutable_references),
+        &constructor_args,
+        &[],
+        sourcemap,
+        constructor_sourcemap,
+        codegen_options,
+    )?;
+    log.extend(warnings);
+    Ok(log)
+}
 
-    println!("Writing contracts to eEVM at {}", evm_path.display());
+/// Translates a single contract (`selected`) or all contracts (`None`) of bytecode or compiler
+/// output.
+fn translate_input(
+    matches: &ArgMatches,
+    output: &OutputLayout,
+    input: &str,
+    selected: Option<&str>,
+    constructor_path: Option<&str>,
+    codegen_options: &CodegenOptions,
+) -> anyhow::Result<()> {
+    println!("reading input {}", input);
+    let libraries = libraries(matches)?;
+    let immutables = immutables(matches)?;
+    let forced_format = if matches.is_present("single-combined-json") {
+        Some(JsonFormat::SingleContract)
+    } else if matches.is_present("combined-json") {
+        Some(JsonFormat::Combined)
+    } else if matches.is_present("standard-json") {
+        Some(JsonFormat::StandardJson)
+    } else if matches.is_present("vyper") {
+        Some(JsonFormat::Vyper)
+    } else if input.ends_with("combined.json") {
+        Some(JsonFormat::Combined)
+    } else {
+        None
+    };
+    let format = json_format(input, forced_format)?;
 
-    if matches.is_present("single-combined-json") {
-        let name = matches
-            .value_of("name")
-            .ok_or(anyhow!("Missing contract name"))?;
+    if format == Some(JsonFormat::SingleContract) {
+        let name = selected.ok_or_else(|| {
+            anyhow!("a single-contract JSON file does not contain the contract name - use `translate <name> <input>`")
+        })?;
 
         let contract = read_single_contract_combined_from_file(input)?;
 
@@ -129,47 +906,58 @@ fn main() -> anyhow::Result<()> {
             None
         };
 
-        let bytecode = contract.bin_runtime.trim();
-        let bytecode = to_hex(bytecode).with_context(|| {
-            format!(
-                "failed to convert bytecode of contract {} in combined.json from hex",
-                name
-            )
-        })?;
+        let bytecode =
+            runtime_code(name, &contract, &libraries, &immutables).with_context(|| {
+                format!(
+                    "failed to convert bytecode of contract {} in combined.json from hex",
+                    name
+                )
+            })?;
 
         let constructor_bytecode = contract.bin.trim();
-        let constructor_bytecode = to_hex(constructor_bytecode).with_context(|| {
-            format!(
+        let constructor_bytecode =
+            link_bytecode(constructor_bytecode, &libraries, &contract.link_references)
+                .and_then(|b| to_hex(&b))
+                .with_context(|| {
+                    format!(
                 "failed to convert constructor bytecode of contract {} in combined.json from hex",
                 name
             )
-        })?;
+                })?;
 
-        write_abi(name, evm_path, contract.abi.as_bytes())?;
+        let abi_path = write_abi(name, output, contract.abi.as_bytes())?;
+        println!("Writing ABI to {}", abi_path.display());
+        let constructor_args = constructor_args(matches, Some(&contract.abi))?;
 
         //(bytecode, Some(constructor_bytecode), sourcemap)
 
         println!("Translating contract {} to C++...", name);
-        println!("Writing contract to eEVM {}", evm_path.display());
-        translate_to_c(
-            evm_path,
+        println!("Writing contract to {}", output.root().display());
+        let warnings = translate_to_c(
+            output,
             name,
             bytecode,
             Some(constructor_bytecode),
+            &immutable_ranges(&contract.immutable_references),
+            &constructor_args,
+            &[],
             sourcemap,
-            matches.is_present("clang-format"),
+            None,
+            codegen_options,
         )?;
-    } else if input.ends_with("combined.json") || matches.is_present("combined-json") {
+        warnings.iter().for_each(|w| println!("{}", w));
+    } else if let Some(format) = format {
         let combined_path = Path::new(input);
-        let combined = read_combined_from_file(input)?;
+        let combined = read_contracts_from_file(input, format)?;
+        if format == JsonFormat::Foundry && matches.is_present("emit-sourcemap") {
+            println!("[WARNING] Cannot emit source(-map) information for Foundry artifacts, since they do not contain the source list! Use the build-info files instead (forge build --build-info).");
+        }
 
-        let name_best_match = if matches.is_present("translate-all") {
-            None
-        } else {
+        let name_best_match = if let Some(selected) = selected {
             let look_for_name = if let Some(lname) = matches.value_of("contract-name") {
                 lname
             } else {
-                matches.value_of("name").unwrap()
+                selected
             }
             .to_string();
 
@@ -247,23 +1035,28 @@ fn main() -> anyhow::Result<()> {
             }
 
             best_match
+        } else {
+            None
         };
 
-        for (name, contract) in combined.contracts.iter() {
-            let name = if let Some(s) = name.split(":").skip(1).next() {
+        let mut jobs = vec![];
+        // contracts of different files may share a name, but not their output files
+        let mut identifiers = std::collections::BTreeSet::new();
+        for (key, contract) in combined.contracts.iter() {
+            let name = if let Some(s) = key.split(":").skip(1).next() {
                 s.to_string()
             } else {
-                name.clone()
+                key.clone()
             };
             let mut identifier = name.clone();
 
-            if !matches.is_present("translate-all") {
+            if let Some(selected) = selected {
                 if let Some(bmatch) = name_best_match.as_ref() {
                     if &name == bmatch {
                         identifier = if let Some(lname) = matches.value_of("contract-name") {
                             lname
                         } else {
-                            matches.value_of("name").unwrap()
+                            selected
                         }
                         .to_string();
 
@@ -277,78 +1070,73 @@ fn main() -> anyhow::Result<()> {
                 }
             }
 
-            let sourcemap = if matches.is_present("emit-sourcemap") {
-                println!("Emitting source(-map) information to contract!");
-                let x = if let Some(parent) = combined_path.parent() {
-                    let filepaths: Vec<PathBuf> = combined
-                        .source_list
-                        .iter()
-                        .cloned()
-                        .map(|s| parent.join(s))
-                        .collect();
-                    let files: Vec<&str> = filepaths.iter().map(|s| s.to_str().unwrap()).collect();
-                    parse_source_map(&contract.srcmap_runtime, &files)
-                } else {
-                    let files: Vec<&str> = combined.source_list.iter().map(|s| &**s).collect();
-                    parse_source_map(&contract.srcmap_runtime, &files)
-                }
-                .with_context(|| {
-                    format!(
-                        "failed to parse sourcemap from combined.json at {:?}",
-                        combined_path
-                    )
-                })?;
-                Some(x)
-            } else {
-                None
-            };
-
-            let bytecode = contract.bin_runtime.trim();
-            let bytecode = to_hex(bytecode).with_context(|| {
-                format!(
-                    "failed to convert bytecode of contract {} in combined.json from hex",
-                    name
-                )
-            })?;
+            if !identifiers.insert(identifier.clone()) {
+                println!(
+                    "[WARNING] skipping {} - a contract named {} is already translated",
+                    key, identifier
+                );
+                continue;
+            }
+            jobs.push(CombinedJob {
+                name,
+                identifier,
+                contract,
+            });
+            if selected.is_some() {
+                break;
+            }
+        }
 
-            let constructor_bytecode = contract.bin.trim();
-            let constructor_bytecode = to_hex(constructor_bytecode).with_context(|| {
-                format!(
-                "failed to convert constructor bytecode of contract {} in combined.json from hex",
-                name
+        if matches.is_present("emit-sourcemap") {
+            println!("Emitting source(-map) information to contract!");
+        }
+        // validated by the argument parser
+        let threads = match matches.get_one::<u64>("jobs") {
+            Some(&j) => j as usize,
+            None => default_jobs(),
+        };
+        let results = map_parallel(&jobs, threads, |job| {
+            translate_combined_contract(
+                matches,
+                output,
+                combined_path,
+                &combined,
+                job,
+                &libraries,
+                &immutables,
+                codegen_options,
             )
-            })?;
+        });
 
-            write_abi(&identifier, evm_path, contract.abi.as_bytes())?;
-            println!(
-                "Translating contract with name {} (identifier {}) to C++...",
-                name, identifier
-            );
-            translate_to_c(
-                evm_path,
-                &identifier,
-                bytecode,
-                Some(constructor_bytecode),
-                sourcemap,
-                matches.is_present("clang-format"),
-            )?;
-
-            if !matches.is_present("translate-all") {
-                break;
+        // report in the order of the compiler output, independent of the scheduling
+        let mut failed = 0;
+        for (job, result) in jobs.iter().zip(results) {
+            match result {
+                Ok(log) => log.iter().for_each(|line| println!("{}", line)),
+                Err(e) if selected.is_some() => return Err(e),
+                Err(e) => {
+                    println!("[ERROR] failed to translate contract {}: {:#}", job.name, e);
+                    failed += 1;
+                }
             }
         }
+        if failed > 0 {
+            bail!("failed to translate {} of {} contracts", failed, jobs.len());
+        }
     } else {
-        let name = matches
-            .value_of("name")
-            .ok_or(anyhow!("Missing contract name"))?;
+        let name = selected.ok_or_else(|| {
+            anyhow!(
+                "translating all contracts requires a compiler output as input, but {} is bytecode",
+                input
+            )
+        })?;
 
-        let bytecode = std::fs::read_to_string(input)
+        let bytecode = read_bytecode(input)
             .with_context(|| format!("failed to read bytecode data from {}", input))?;
-        let bytecode = bytecode.trim();
-        let bytecode = to_hex(bytecode)
+        let bytecode = link_bytecode(&bytecode, &libraries, &LinkReferences::new())
+            .and_then(|b| to_hex(&b))
             .with_context(|| format!("failed to convert bytecode file {} from hex", input))?;
-        let constructor_file = if let Some(constructor_path) = matches.value_of("constructor_path")
-        {
+        let constructor_file = if let Some(constructor_path) = constructor_path {
             Some(std::path::Path::new(constructor_path))
         } else {
             let suffix = "-runtime";
@@ -365,22 +1153,25 @@ fn main() -> anyhow::Result<()> {
             }
         };
         let constructor_bytecode = if let Some(constructor_file) = constructor_file {
-            let chex = std::fs::read_to_string(constructor_file)?;
-            let chex = chex.trim();
-            let cbytes = to_hex(&chex).with_context(|| {
-                format!(
-                    "failed to convert constructor file {:?} from hex",
-                    constructor_file
-                )
-            })?;
+            let chex = read_bytecode(constructor_file.to_str().unwrap())?;
+            let cbytes = link_bytecode(&chex, &libraries, &LinkReferences::new())
+                .and_then(|b| to_hex(&b))
+                .with_context(|| {
+                    format!(
+                        "failed to convert constructor file {:?} from hex",
+                        constructor_file
+                    )
+                })?;
             Some(cbytes)
         } else {
             None
         };
 
-        if let Some(abi) = matches.value_of("abi") {
+        let abi = if let Some(abi) = matches.value_of("abi") {
             let abi = std::fs::read_to_string(abi)?;
-            write_abi(name, evm_path, abi.as_bytes())?
+            let abi_path = write_abi(name, output, abi.as_bytes())?;
+            println!("Writing ABI to {}", abi_path.display());
+            Some(abi)
         } else {
             let suffix = "bin-runtime";
             if input.ends_with(suffix) {
@@ -388,24 +1179,449 @@ fn main() -> anyhow::Result<()> {
                 if cpath.exists() {
                     println!("[INFO] Reading ABI data from {}", cpath.display());
                     let abi = std::fs::read_to_string(cpath)?;
-                    write_abi(name, evm_path, abi.as_bytes())?
+                    let abi_path = write_abi(name, output, abi.as_bytes())?;
+                    println!("Writing ABI to {}", abi_path.display());
+                    Some(abi)
+                } else {
+                    None
                 }
+            } else {
+                None
             }
-        }
+        };
+        let constructor_args = constructor_args(matches, abi.as_deref())?;
 
         //(bytecode, constructor_bytecode, None)
         println!("Translating contract {} to C++...", name);
-        println!("Writing contract to eEVM {}", evm_path.display());
-        translate_to_c(
-            evm_path,
+        println!("Writing contract to {}", output.root().display());
+        let warnings = translate_to_c(
+            output,
             name,
             bytecode,
             constructor_bytecode,
+            &[],
+            &constructor_args,
+            &[],
             None,
-            matches.is_present("clang-format"),
+            None,
+            codegen_options,
         )?;
+        warnings.iter().for_each(|w| println!("{}", w));
     };
 
-    println!("Done!");
     Ok(())
 }
+
+/// Input and code generation options shared by the translate subcommands and the invocation
+/// without subcommand.
+fn translate_args(cmd: Command<'static>) -> Command<'static> {
+    cmd.arg(arg!(-c --"combined-json" "force use of combined json as input (auto-detected on filetype)"))
+        .arg(arg!(-C --"single-combined-json" "force use of combined json of a single contract (i.e., truffle-style)"))
+        .arg(arg!(-j --"standard-json" "force use of solc standard-JSON output or Hardhat/Foundry build-info files as input (auto-detected on file contents)"))
+        .arg(arg!(--vyper "force use of vyper output as input (combined_json or the plain output of -f bytecode_runtime,abi,source_map)"))
+        .group(ArgGroup::new("input-format").args(&["combined-json", "single-combined-json", "standard-json", "vyper"]))
+        .arg(arg!(-e --"evm-path" [EVM_PATH] "path to eEVM project").default_value("./eEVM").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(-O --"out-dir" [OUT_DIR] "write the generated files into a self-contained directory with the layout of the eEVM tree and a manifest.json instead of an eEVM project").conflicts_with("evm-path").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(-s --"emit-sourcemap" "emit source information to generated code for easier codegen debugging"))
+        .arg(arg!(-L --"line-directives" "emit #line directives pointing to the solidity sources into the generated code (requires --emit-sourcemap)").requires("emit-sourcemap"))
+        .arg(arg!(--"constructor-args" [HEX] "ABI encoded constructor arguments that are baked into the generated code").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(--"constructor-args-json" [JSON] "constructor arguments as JSON array, which are ABI encoded according to the constructor in the ABI definition").conflicts_with("constructor-args").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(--link [LIBRARY] "address of a linked library as Name=0xaddress (the name may be fully qualified, i.e., file.sol:Name)").multiple_values(false).multiple_occurrences(true))
+        .arg(arg!(--immutable [IMMUTABLE] "value of an immutable variable as id=value, where id is the AST id from the immutableReferences of the compiler output").multiple_values(false).multiple_occurrences(true))
+        .arg(arg!(--"translate-constructor" "also translate the constructor (init code) to a construct() method instead of interpreting it"))
+        .arg(arg!(--"emit-asm" "write a disassembly listing of the runtime code to contracts/<name>.evm (can be used as input again)"))
+        .arg(arg!(--"build-fragment" [FORMAT] "write a build-system fragment listing the generated files of every contract: contracts/<name>.cmake (included by contracts/evm2cpp.cmake) or contracts/<name>.build.json").possible_values(["cmake", "json"]).multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(-F --"clang-format" "launch clang-format on generated code"))
+        .arg(arg!(--jobs [N] "number of contracts translated in parallel when translating all contracts of a compiler output (defaults to the number of CPUs)").value_parser(clap::value_parser!(u64).range(1..)).multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(--"trace-comparisons" [MODE] "comparison tracing mode: 'dict' traces only comparisons of two runtime values, 'cmplog' traces all comparison-like instructions with per-site IDs").possible_values(["dict", "cmplog"]).default_value("dict").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(--"trace-state" "emit TRACE_SLOAD/TRACE_SSTORE/TRACE_MEM hooks for storage and memory accesses (no-ops unless defined by the harness)"))
+        .arg(arg!(--"trace-calls" "emit before_external_call/after_external_call hooks around CALL/CALLCODE/DELEGATECALL/STATICCALL/CREATE/CREATE2"))
+}
+
+/// Arguments of the subcommands that analyze the runtime code of a single contract.
+fn code_args(cmd: Command<'static>) -> Command<'static> {
+    cmd.arg(
+        arg!(-o --output [OUTPUT] "write the output to a file instead of stdout")
+            .multiple_values(false)
+            .multiple_occurrences(false),
+    )
+    .arg(
+        arg!(--"contract-name" [NAME] "contract to select from the compiler output")
+            .multiple_values(false)
+            .multiple_occurrences(false),
+    )
+    .arg(
+        arg!(--link [LIBRARY] "address of a linked library as Name=0xaddress")
+            .multiple_values(false)
+            .multiple_occurrences(true),
+    )
+    .arg(arg!(<input> "runtime code (hex, raw binary or .evm; - for stdin) or compiler output"))
+}
+
+fn cli() -> Command<'static> {
+    let app = Command::new(env!("CARGO_BIN_NAME"))
+        .about("EVM bytecode to C++ transpiler targeting the eEVM framework")
+        .version(env!("CARGO_PKG_VERSION"))
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(translate_args(
+            Command::new("translate")
+                .about("Translate a single contract to C++")
+                .arg(arg!(-a --abi [ABI_FILE] "path to abi definition file (for bytecode input)").multiple_values(false).multiple_occurrences(false))
+                .arg(arg!(--"contract-name" [NAME] "contract to select from the compiler output (defaults to <name>)").multiple_values(false).multiple_occurrences(false))
+                .arg(arg!(--alloc "<input> is a state dump (geth genesis alloc, geth dump or anvil --dump-state) and <name> the address of the account to translate").conflicts_with_all(&["abi", "constructor_path"]))
+                .arg(arg!(<name> "name/identifier of the contract for the generated code (the contract name for a Foundry output directory, the account address with --alloc)"))
+                .arg(arg!(<input> "path to EVM runtime code (.bin-runtime, hex or raw binary; - for stdin), EVM assembly (.evm), compiler output or Foundry output directory"))
+                .arg(arg!([constructor_path] "path to EVM constructor code (.bin)")),
+        ))
+        .subcommand(translate_args(
+            Command::new("translate-all")
+                .about("Translate all contracts of a compiler output, Foundry project (contracts in src/) or state dump to C++")
+                .arg(arg!(--alloc "<input> is a state dump (geth genesis alloc, geth dump or anvil --dump-state); translate all accounts with code including their storage"))
+                .arg(arg!(<input> "compiler output, Foundry output directory or state dump (with --alloc)")),
+        ))
+        .subcommand(code_args(
+            Command::new("disasm")
+                .about("Print a disassembly listing of the runtime code (basic blocks, push data, metadata, optionally folded constants and source locations)")
+                .arg(arg!(-f --format [FORMAT] "output format").possible_values(["text", "json"]).default_value("text"))
+                .arg(arg!(-s --source "annotate instructions with their source location and snippet (requires compiler output with sourcemap)"))
+                .arg(arg!(--constants "annotate instructions with the operands and results known after constant folding")),
+        ))
+        .subcommand(code_args(
+            Command::new("cfg")
+                .about("Write the control flow graph of the runtime code (Graphviz dot or JSON)")
+                .arg(arg!(-f --format [FORMAT] "output format").possible_values(["dot", "json"]).default_value("dot")),
+        ))
+        .subcommand(code_args(
+            Command::new("stats")
+                .about("Print statistics about the runtime code (size, basic blocks, resolved jumps, folded constants, opcodes)")
+                .arg(arg!(-f --format [FORMAT] "output format").possible_values(["text", "json"]).default_value("text")),
+        ))
+        .subcommand(code_args(
+            Command::new("dict")
+                .about("Write the fuzzing dictionary (AFL/libFuzzer format) of the runtime code without translating it"),
+        ))
+        .subcommand(
+            Command::new("remove")
+                .about("Delete the generated files of contracts and unregister them from contracts.h (and the manifest of --out-dir)")
+                .arg(arg!(-e --"evm-path" [EVM_PATH] "path to eEVM project").default_value("./eEVM").multiple_values(false).multiple_occurrences(false))
+                .arg(arg!(-O --"out-dir" [OUT_DIR] "self-contained output directory the contracts were written to").conflicts_with("evm-path").multiple_values(false).multiple_occurrences(false))
+                .arg(arg!(<name> ... "name/identifier of the contract")),
+        )
+        .subcommand(
+            Command::new("coverage")
+                .about("Attribute basic block coverage to solidity source lines (LCOV/Cobertura)")
+                .arg(arg!(-b --"bb-list" <BB_LIST> "basic block list of the contract (.bb_list file written during translation)"))
+                .arg(arg!(-H --hits <HITS> "file with the addresses of executed basic blocks (one per line, optionally followed by a hit count)"))
+                .arg(arg!(-f --format [FORMAT] "output format").possible_values(["lcov", "cobertura"]).default_value("lcov"))
+                .arg(arg!(-o --output [OUTPUT] "path of the coverage report").default_value("coverage.info"))
+                .arg(arg!(--"contract-name" [NAME] "contract to select from the combined.json input").multiple_values(false).multiple_occurrences(false))
+                .arg(arg!(--link [LIBRARY] "address of a linked library as Name=0xaddress").multiple_values(false).multiple_occurrences(true))
+                .arg(arg!(<input> "combined-json input the contract was translated from")),
+        );
+
+    legacy_args(app)
+}
+
+/// The invocation without subcommand, which is kept for backwards compatibility.
+fn legacy_args(cmd: Command<'static>) -> Command<'static> {
+    translate_args(cmd)
+        .arg(arg!(-a --abi [ABI_FILE] "path to abi definition file").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(-A --"translate-all" "Translate all contracts found in combined.json (the compiler output is then the only positional argument)"))
+        .arg(arg!(--"contract-name" [NAME] "contract name to look for in the combined.json input format (defaults to the <name> parameter)").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(--"foundry-out" [OUT_DIR] "translate the contracts of a Foundry project from its output directory (all contracts in src/ or only the contract given as <name>)").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!(--alloc [ALLOC_FILE] "translate the accounts with code of a state dump (geth genesis alloc, geth dump or anvil --dump-state) including their storage (all accounts or only the address given as <name>)").conflicts_with("foundry-out").multiple_values(false).multiple_occurrences(false))
+        .arg(arg!([name] "name/identifier of the contract for the generated code").required_unless_present_any(["foundry-out", "alloc"]))
+        .arg(arg!([input] "path to EVM runtime code (.bin-runtime, hex or raw binary; - for stdin), EVM assembly (.evm) or combined-json input"))
+        .arg(arg!([constructor_path] "path to EVM constructor code (.bin)"))
+}
+
+/// Only the invocation without subcommand, for contracts that are named like a subcommand.
+fn legacy_cli() -> Command<'static> {
+    legacy_args(Command::new(env!("CARGO_BIN_NAME")).version(env!("CARGO_PKG_VERSION")))
+}
+
+/// Parses the command line. If the first argument is the name of a subcommand, it is parsed as
+/// such. Only if that fails, the arguments are parsed as the legacy invocation of a contract with
+/// that name (e.g., `evm2cpp stats stats.bin-runtime stats.bin`).
+fn parse_args<I, T>(args: I) -> clap::Result<ArgMatches>
+where
+    I: IntoIterator<Item = T>,
+    T: Into<OsString>,
+{
+    let args: Vec<OsString> = args.into_iter().map(Into::into).collect();
+    cli().try_get_matches_from(&args).or_else(|e| {
+        let names_subcommand = args
+            .get(1)
+            .is_some_and(|a| cli().find_subcommand(a.as_os_str()).is_some());
+        // help and version are not errors (and not written to stderr)
+        if names_subcommand && e.use_stderr() {
+            legacy_cli().try_get_matches_from(&args).map_err(|_| e)
+        } else {
+            Err(e)
+        }
+    })
+}
+
+/// Returns a warning if a subcommand invocation was probably meant as the invocation without
+/// subcommand of a contract named like the subcommand, i.e., if it is valid as such and its input
+/// is an existing file named after the subcommand (e.g., `evm2cpp stats stats.bin-runtime`).
+fn legacy_ambiguity_warning(args: &[OsString], matches: &ArgMatches) -> Option<String> {
+    let subcommand = matches.subcommand_name()?;
+    if args.get(1)?.to_str() != Some(subcommand) {
+        return None;
+    }
+    let input = Path::new(args.get(2)?);
+    let file_name = input.file_name()?.to_str()?;
+    if !(input.is_file() && file_name.starts_with(subcommand))
+        || legacy_cli().try_get_matches_from(args).is_err()
+    {
+        return None;
+    }
+    Some(format!(
+        "[WARNING] running the {0} subcommand on {1}. The invocation without subcommand is deprecated; to translate a contract named {0}, use `{2} translate {0} {1} ...`",
+        subcommand,
+        input.display(),
+        env!("CARGO_BIN_NAME")
+    ))
+}
+
+fn main() -> anyhow::Result<()> {
+    let args: Vec<OsString> = std::env::args_os().collect();
+    let matches = parse_args(&args).unwrap_or_else(|e| e.exit());
+    if let Some(warning) = legacy_ambiguity_warning(&args, &matches) {
+        // on stderr, since the analysis subcommands write their output to stdout
+        eprintln!("{}", warning);
+    }
+
+    match matches.subcommand() {
+        Some(("translate", sub_matches)) => {
+            translate_main(sub_matches, Translation::from_translate(sub_matches))
+        }
+        Some(("translate-all", sub_matches)) => {
+            translate_main(sub_matches, Translation::from_translate_all(sub_matches))
+        }
+        Some(("disasm", sub_matches)) => disasm_main(sub_matches),
+        Some(("cfg", sub_matches)) => cfg_main(sub_matches),
+        Some(("stats", sub_matches)) => stats_main(sub_matches),
+        Some(("dict", sub_matches)) => dict_main(sub_matches),
+        Some(("remove", sub_matches)) => remove_main(sub_matches),
+        Some(("coverage", sub_matches)) => coverage_main(sub_matches),
+        _ => translate_main(&matches, Translation::from_legacy(&matches)?),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn legacy(args: &[&str]) -> anyhow::Result<String> {
+        let matches = cli().try_get_matches_from(args)?;
+        Translation::from_legacy(&matches).map(|t| format!("{:?}", t))
+    }
+
+    #[test]
+    fn cli_definition() {
+        cli().debug_assert();
+    }
+
+    #[test]
+    fn legacy_invocation() {
+        assert_eq!(
+            legacy(&["evm2cpp", "Foo", "foo.bin-runtime", "foo.bin"]).unwrap(),
+            format!(
+                "{:?}",
+                Translation::Input {
+                    input: "foo.bin-runtime",
+                    name: Some("Foo"),
+                    constructor_path: Some("foo.bin")
+                }
+            )
+        );
+        assert_eq!(
+            legacy(&["evm2cpp", "-A", "combined.json"]).unwrap(),
+            format!(
+                "{:?}",
+                Translation::Input {
+                    input: "combined.json",
+                    name: None,
+                    constructor_path: None
+                }
+            )
+        );
+        assert_eq!(
+            legacy(&["evm2cpp", "--alloc", "alloc.json", "0xaa"]).unwrap(),
+            format!(
+                "{:?}",
+                Translation::Alloc {
+                    path: "alloc.json",
+                    address: Some("0xaa")
+                }
+            )
+        );
+
+        let err = legacy(&["evm2cpp", "Foo"]).unwrap_err().to_string();
+        assert!(err.contains("missing <input>"), "{}", err);
+        let err = legacy(&["evm2cpp", "-A", "combined.json", "Foo"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("takes only the compiler output"), "{}", err);
+        let err = legacy(&["evm2cpp", "--foundry-out", "out", "Foo", "foo.bin"])
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("only the contract name"), "{}", err);
+        assert!(legacy(&["evm2cpp", "-c", "-j", "Foo", "combined.json"]).is_err());
+    }
+
+    #[test]
+    fn subcommands() {
+        let matches = cli()
+            .try_get_matches_from(["evm2cpp", "translate-all", "--alloc", "alloc.json"])
+            .unwrap();
+        let (_, sub_matches) = matches.subcommand().unwrap();
+        assert_eq!(
+            Translation::from_translate_all(sub_matches),
+            Translation::Alloc {
+                path: "alloc.json",
+                address: None
+            }
+        );
+
+        // translate-all has no <name>, the legacy flags are not available for subcommands
+        assert!(cli()
+            .try_get_matches_from(["evm2cpp", "translate-all", "Foo", "combined.json"])
+            .is_err());
+        assert!(cli()
+            .try_get_matches_from(["evm2cpp", "translate", "-A", "Foo", "foo.bin"])
+            .is_err());
+        assert!(cli()
+            .try_get_matches_from(["evm2cpp", "cfg", "-f", "svg", "foo.bin"])
+            .is_err());
+    }
+
+    #[test]
+    fn jobs_validation() {
+        // --jobs is validated the same way for every invocation that accepts it
+        for args in [
+            &["evm2cpp", "translate-all", "combined.json"][..],
+            &["evm2cpp", "translate", "Foo", "foo.bin"],
+            &["evm2cpp", "Foo", "foo.bin"],
+            &["evm2cpp", "-A", "combined.json"],
+        ] {
+            let with_jobs = |jobs: &str| {
+                let mut args = args.to_vec();
+                args.extend(["--jobs", jobs]);
+                parse_args(args)
+            };
+            let matches = with_jobs("4").unwrap();
+            let matches = matches.subcommand().map_or(&matches, |(_, m)| m);
+            assert_eq!(matches.get_one::<u64>("jobs"), Some(&4));
+            for invalid in ["0", "-1", "x"] {
+                assert!(with_jobs(invalid).is_err(), "{:?} --jobs {}", args, invalid);
+            }
+        }
+    }
+
+    #[test]
+    fn translate_all_same_name() {
+        let dir = testutil::TestDir::new("translate_all_same_name");
+        let input = dir.join("combined.json");
+        let contract = |abi: &str| {
+            format!(
+                r#"{{"abi": "{}", "bin": "00", "bin-runtime": "00"}}"#,
+                abi.replace('"', "\\\"")
+            )
+        };
+        std::fs::write(
+            &input,
+            format!(
+                r#"{{"contracts": {{"a.sol:Foo": {}, "b.sol:Bar": {}, "b.sol:Foo": {}}}}}"#,
+                contract("[]"),
+                contract("[]"),
+                contract(r#"[{"type": "fallback"}]"#)
+            ),
+        )
+        .unwrap();
+        let out_dir = dir.join("out");
+        let args: Vec<OsString> = vec![
+            "evm2cpp".into(),
+            "translate-all".into(),
+            input.into(),
+            "-O".into(),
+            out_dir.clone().into(),
+        ];
+        let matches = parse_args(&args).unwrap();
+        let (_, sub_matches) = matches.subcommand().unwrap();
+
+        // the second Foo is skipped instead of writing the same files concurrently
+        translate_main(sub_matches, Translation::from_translate_all(sub_matches)).unwrap();
+        let abi = |name: &str| std::fs::read_to_string(out_dir.join("fuzz/abi").join(name));
+        assert_eq!(abi("Foo.abi").unwrap(), "[]");
+        assert!(abi("Bar.abi").is_ok());
+    }
+
+    #[test]
+    fn contract_named_like_subcommand() {
+        // parses as subcommand, so `translate stats stats.bin-runtime` is required here
+        let matches = parse_args(["evm2cpp", "stats", "stats.bin-runtime"]).unwrap();
+        assert_eq!(matches.subcommand_name(), Some("stats"));
+
+        // not valid for the subcommand, hence the legacy translation of a contract named `stats`
+        let matches = parse_args([
+            "evm2cpp",
+            "stats",
+            "stats.bin-runtime",
+            "stats.bin",
+            "-e",
+            "eEVM",
+        ])
+        .unwrap();
+        assert_eq!(matches.subcommand_name(), None);
+        assert_eq!(
+            Translation::from_legacy(&matches).unwrap(),
+            Translation::Input {
+                input: "stats.bin-runtime",
+                name: Some("stats"),
+                constructor_path: Some("stats.bin")
+            }
+        );
+        let matches = parse_args(["evm2cpp", "remove", "remove.combined.json", "-s"]).unwrap();
+        assert_eq!(matches.value_of("name"), Some("remove"));
+
+        // errors of the subcommand are kept if the legacy invocation is invalid as well
+        let err = parse_args(["evm2cpp", "cfg", "-f", "svg", "foo.bin"]).unwrap_err();
+        assert!(err.to_string().contains("svg"), "{}", err);
+    }
+
+    #[test]
+    fn contract_named_like_subcommand_warning() {
+        let dir = testutil::TestDir::new("contract_named_like_subcommand_warning");
+        let stats = dir.join("stats.bin-runtime");
+        let other = dir.join("foo.bin-runtime");
+        std::fs::write(&stats, "0x00").unwrap();
+        std::fs::write(&other, "0x00").unwrap();
+        let warning = |input: &Path| {
+            let args: Vec<OsString> = vec!["evm2cpp".into(), "stats".into(), input.into()];
+            let matches = parse_args(&args).unwrap();
+            assert_eq!(matches.subcommand_name(), Some("stats"));
+            legacy_ambiguity_warning(&args, &matches)
+        };
+
+        // the subcommand wins, but the legacy translation of a contract `stats` is likely meant
+        let w = warning(&stats).unwrap();
+        assert!(w.contains("evm2cpp translate stats"), "{}", w);
+        // an input not named after the subcommand, or one that does not exist, is unambiguous
+        assert_eq!(warning(&other), None);
+        assert_eq!(warning(&dir.join("stats.bin")), None);
+
+        // no warning for the explicit form
+        let args: Vec<OsString> = vec![
+            "evm2cpp".into(),
+            "translate".into(),
+            "stats".into(),
+            stats.clone().into(),
+        ];
+        let matches = parse_args(&args).unwrap();
+        assert_eq!(legacy_ambiguity_warning(&args, &matches), None);
+    }
+}
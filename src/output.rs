@@ -0,0 +1,569 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+/// Name of the manifest in `--out-dir` mode.
+pub const MANIFEST_FILE: &str = "manifest.json";
+
+/// Name of the CMake file that includes the fragments of all contracts.
+pub const CMAKE_INDEX_FILE: &str = "contracts/evm2cpp.cmake";
+
+/// Build-system fragment listing the generated files of a contract (`--build-fragment`).
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BuildFragment {
+    /// `contracts/<name>.cmake`, included by `contracts/evm2cpp.cmake`
+    CMake,
+    /// `contracts/<name>.build.json`
+    Json,
+}
+
+impl std::str::FromStr for BuildFragment {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cmake" => Ok(BuildFragment::CMake),
+            "json" => Ok(BuildFragment::Json),
+            _ => Err(anyhow!("unknown build fragment format {:?}", s)),
+        }
+    }
+}
+
+/// First line of a `contracts.h` written by evm2cpp.
+const CONTRACTS_HEADER_MARKER: &str = "// auto-generated by evm2cpp";
+
+/// Whether the line of `contracts.h` includes the header of a translated contract.
+fn is_contract_include(line: &str) -> bool {
+    let line = line.trim();
+    line.starts_with("#include \"eEVM/evm2cpp/contracts/") && line.ends_with(".h\"")
+}
+
+/// Directories of the eEVM tree that generated files are written to.
+const DIRECTORIES: [&str; 4] = [
+    "contracts",
+    "include/eEVM/evm2cpp/contracts",
+    "fuzz/abi",
+    "fuzz/dict",
+];
+
+/// Lists the generated files per contract, relative to the output directory, such that they can
+/// be integrated into an eEVM checkout later on.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Manifest {
+    pub generator: String,
+    /// contract identifier -> kind of file (`source`, `header`, `abi`, ...) -> relative path
+    pub contracts: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+/// Where the generated files are written. By default into an existing eEVM checkout, or with
+/// `--out-dir` into a self-contained directory with the same layout and a manifest.
+#[derive(Clone, Debug)]
+pub struct OutputLayout {
+    root: PathBuf,
+    standalone: bool,
+    /// serializes the updates of the files shared by all contracts (`contracts.h`, the CMake index
+    /// and the manifest) when contracts are translated in parallel
+    lock: Arc<Mutex<()>>,
+}
+
+impl OutputLayout {
+    /// Writes into the eEVM checkout at the given path, which must already contain the directory
+    /// structure.
+    pub fn eevm(path: &Path) -> Self {
+        OutputLayout {
+            root: path.to_path_buf(),
+            standalone: false,
+            lock: Arc::default(),
+        }
+    }
+
+    /// Writes into a self-contained output directory, which is created if necessary.
+    pub fn out_dir(path: &Path) -> anyhow::Result<Self> {
+        for dir in DIRECTORIES.iter() {
+            let dir = path.join(dir);
+            std::fs::create_dir_all(&dir)
+                .with_context(|| format!("failed to create directory {}", dir.display()))?;
+        }
+        let layout = OutputLayout {
+            root: path.to_path_buf(),
+            standalone: true,
+            lock: Arc::default(),
+        };
+        layout.update_contracts_header()?;
+        Ok(layout)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn is_standalone(&self) -> bool {
+        self.standalone
+    }
+
+    pub fn contract_source(&self, contract_id: &str) -> PathBuf {
+        self.root.join(format!("contracts/{}.cpp", contract_id))
+    }
+
+    pub fn contract_header(&self, contract_id: &str) -> PathBuf {
+        self.root
+            .join(format!("include/eEVM/evm2cpp/contracts/{}.h", contract_id))
+    }
+
+    /// The header that includes the headers of all translated contracts.
+    pub fn contracts_header(&self) -> PathBuf {
+        self.root.join("include/eEVM/evm2cpp/contracts.h")
+    }
+
+    pub fn bb_list(&self, contract_id: &str) -> PathBuf {
+        self.root.join(format!("contracts/{}.bb_list", contract_id))
+    }
+
+    pub fn asm(&self, contract_id: &str) -> PathBuf {
+        self.root.join(format!("contracts/{}.evm", contract_id))
+    }
+
+    pub fn abi(&self, contract_id: &str) -> PathBuf {
+        self.root.join(format!("fuzz/abi/{}.abi", contract_id))
+    }
+
+    pub fn dict(&self, contract_id: &str) -> PathBuf {
+        self.root.join(format!("fuzz/dict/{}.dict", contract_id))
+    }
+
+    pub fn cmake_fragment(&self, contract_id: &str) -> PathBuf {
+        self.root.join(format!("contracts/{}.cmake", contract_id))
+    }
+
+    pub fn json_fragment(&self, contract_id: &str) -> PathBuf {
+        self.root
+            .join(format!("contracts/{}.build.json", contract_id))
+    }
+
+    /// Path of a generated file relative to the output directory.
+    pub fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.root)
+            .unwrap_or(path)
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// All generated files of a contract, which might or might not exist.
+    fn contract_files(&self, contract_id: &str) -> Vec<PathBuf> {
+        vec![
+            self.contract_source(contract_id),
+            self.contract_header(contract_id),
+            self.bb_list(contract_id),
+            self.asm(contract_id),
+            self.abi(contract_id),
+            self.dict(contract_id),
+            self.cmake_fragment(contract_id),
+            self.json_fragment(contract_id),
+        ]
+    }
+
+    /// Returns the identifiers of all translated contracts, i.e., of the contract headers in
+    /// `include/eEVM/evm2cpp/contracts/`.
+    pub fn translated_contracts(&self) -> anyhow::Result<BTreeSet<String>> {
+        let dir = self.root.join("include/eEVM/evm2cpp/contracts");
+        let mut contracts = BTreeSet::new();
+        let entries = std::fs::read_dir(&dir)
+            .with_context(|| format!("failed to list directory {}", dir.display()))?;
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|e| e == "h") {
+                if let Some(stem) = path.file_stem().and_then(|s| s.to_str()) {
+                    contracts.insert(stem.to_string());
+                }
+            }
+        }
+        Ok(contracts)
+    }
+
+    /// Regenerates the includes of `contracts.h` from the headers of all translated contracts
+    /// (sorted by name) instead of appending to them. Hence, translating a contract again does not
+    /// duplicate its include and removed contracts disappear from the index. All other lines of an
+    /// existing `contracts.h` (e.g., hand-written includes) are kept.
+    pub fn update_contracts_header(&self) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let path = self.contracts_header();
+        let existing = if path.exists() {
+            std::fs::read_to_string(&path)
+                .with_context(|| format!("failed to read file at {}", path.display()))?
+        } else {
+            String::new()
+        };
+
+        let mut header = format!(
+            "{} - the contract includes are regenerated on every translation\n",
+            CONTRACTS_HEADER_MARKER
+        );
+        for line in existing.lines() {
+            if !line.starts_with(CONTRACTS_HEADER_MARKER) && !is_contract_include(line) {
+                header += line;
+                header += "\n";
+            }
+        }
+        for contract_id in self.translated_contracts()? {
+            header += &format!("#include \"eEVM/evm2cpp/contracts/{}.h\"\n", contract_id);
+        }
+        std::fs::write(&path, header)
+            .with_context(|| format!("failed to write file at {}", path.display()))
+    }
+
+    /// Writes the build-system fragment of a contract, which lists its generated files (given as
+    /// kind and path), and returns the path of the fragment. CMake fragments use paths relative to
+    /// the fragment, such that the output directory can be moved around, and append to the
+    /// `EVM2CPP_CONTRACTS`, `EVM2CPP_SOURCES` and `EVM2CPP_HEADERS` lists.
+    pub fn write_build_fragment(
+        &self,
+        format: BuildFragment,
+        contract_id: &str,
+        files: &[(&str, &Path)],
+    ) -> anyhow::Result<PathBuf> {
+        let (path, fragment) = match format {
+            BuildFragment::CMake => {
+                let mut fragment = format!(
+                    "# auto-generated by evm2cpp for contract {0}\nlist(APPEND EVM2CPP_CONTRACTS \"{0}\")\n",
+                    contract_id
+                );
+                for (kind, file) in files.iter() {
+                    let file = format!("${{CMAKE_CURRENT_LIST_DIR}}/../{}", self.relative(file));
+                    fragment += &match *kind {
+                        "source" => format!("list(APPEND EVM2CPP_SOURCES \"{}\")\n", file),
+                        "header" => format!("list(APPEND EVM2CPP_HEADERS \"{}\")\n", file),
+                        _ => format!(
+                            "set(EVM2CPP_{}_{} \"{}\")\n",
+                            contract_id,
+                            kind.to_ascii_uppercase(),
+                            file
+                        ),
+                    };
+                }
+                (self.cmake_fragment(contract_id), fragment)
+            }
+            BuildFragment::Json => {
+                let directory = self
+                    .root
+                    .canonicalize()
+                    .unwrap_or_else(|_| self.root.clone());
+                let mut fragment = serde_json::json!({
+                    "contract": contract_id,
+                    "directory": directory,
+                    "sources": [],
+                    "headers": [],
+                });
+                for (kind, file) in files.iter() {
+                    let file = self.relative(file);
+                    match *kind {
+                        "source" => fragment["sources"]
+                            .as_array_mut()
+                            .unwrap()
+                            .push(file.into()),
+                        "header" => fragment["headers"]
+                            .as_array_mut()
+                            .unwrap()
+                            .push(file.into()),
+                        _ => fragment[*kind] = file.into(),
+                    }
+                }
+                (
+                    self.json_fragment(contract_id),
+                    serde_json::to_string_pretty(&fragment)? + "\n",
+                )
+            }
+        };
+        std::fs::write(&path, fragment)
+            .with_context(|| format!("failed to write build fragment {}", path.display()))?;
+        if format == BuildFragment::CMake {
+            self.update_cmake_index()?;
+        }
+        Ok(path)
+    }
+
+    /// Regenerates `contracts/evm2cpp.cmake`, which includes the CMake fragments of all contracts
+    /// (sorted by name).
+    pub fn update_cmake_index(&self) -> anyhow::Result<()> {
+        let _guard = self.lock.lock().unwrap();
+        let mut index = String::from(
+            "# auto-generated by evm2cpp - regenerated on every translation, do not edit\n",
+        );
+        for contract_id in self.translated_contracts()? {
+            if self.cmake_fragment(&contract_id).exists() {
+                index += &format!(
+                    "include(\"${{CMAKE_CURRENT_LIST_DIR}}/{}.cmake\")\n",
+                    contract_id
+                );
+            }
+        }
+        let path = self.root.join(CMAKE_INDEX_FILE);
+        std::fs::write(&path, index)
+            .with_context(|| format!("failed to write file at {}", path.display()))
+    }
+
+    /// Deletes the generated files of a contract and unregisters it from `contracts.h` and the
+    /// manifest. Returns the deleted files.
+    pub fn remove(&self, contract_id: &str) -> anyhow::Result<Vec<PathBuf>> {
+        let mut removed = vec![];
+        for path in self.contract_files(contract_id) {
+            if path.exists() {
+                std::fs::remove_file(&path)
+                    .with_context(|| format!("failed to remove {}", path.display()))?;
+                removed.push(path);
+            }
+        }
+
+        let mut registered = false;
+        if self.standalone {
+            let _guard = self.lock.lock().unwrap();
+            let mut manifest = self.read_manifest()?;
+            registered = manifest.contracts.remove(contract_id).is_some();
+            if registered {
+                self.write_manifest(&manifest)?;
+            }
+        }
+        if removed.is_empty() && !registered {
+            bail!(
+                "no generated files of contract {} found in {}",
+                contract_id,
+                self.root.display()
+            );
+        }
+        self.update_contracts_header()?;
+        if self.root.join(CMAKE_INDEX_FILE).exists() {
+            self.update_cmake_index()?;
+        }
+        Ok(removed)
+    }
+
+    fn manifest_path(&self) -> PathBuf {
+        self.root.join(MANIFEST_FILE)
+    }
+
+    /// Reads the manifest of the output directory (empty if it does not exist yet).
+    pub fn read_manifest(&self) -> anyhow::Result<Manifest> {
+        let path = self.manifest_path();
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+        let s = std::fs::read_to_string(&path)
+            .with_context(|| format!("failed to read manifest {}", path.display()))?;
+        serde_json::from_str(&s).with_context(|| format!("invalid manifest {}", path.display()))
+    }
+
+    /// Records generated files of a contract in the manifest; files of the same kind from a
+    /// previous translation of the contract are replaced. Nothing is recorded when writing into an
+    /// eEVM checkout.
+    pub fn record(&self, contract_id: &str, files: &[(&str, &Path)]) -> anyhow::Result<()> {
+        if !self.standalone {
+            return Ok(());
+        }
+        let _guard = self.lock.lock().unwrap();
+        let mut manifest = self.read_manifest()?;
+        manifest.generator = format!("evm2cpp {}", env!("CARGO_PKG_VERSION"));
+        let entry = manifest
+            .contracts
+            .entry(contract_id.to_string())
+            .or_default();
+        for (kind, path) in files.iter() {
+            entry.insert(kind.to_string(), self.relative(path));
+        }
+
+        self.write_manifest(&manifest)
+    }
+
+    fn write_manifest(&self, manifest: &Manifest) -> anyhow::Result<()> {
+        let path = self.manifest_path();
+        std::fs::write(&path, serde_json::to_string_pretty(manifest)? + "\n")
+            .with_context(|| format!("failed to write manifest {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TestDir;
+
+    #[test]
+    fn out_dir_manifest() {
+        let tmp = TestDir::new("out_dir_manifest");
+        let dir = tmp.join("out");
+        let layout = OutputLayout::out_dir(&dir).unwrap();
+        assert!(dir.join("include/eEVM/evm2cpp/contracts").is_dir());
+        assert!(layout.contracts_header().is_file());
+
+        layout
+            .record("Foo", &[("abi", &layout.abi("Foo"))])
+            .unwrap();
+        layout
+            .record(
+                "Foo",
+                &[
+                    ("source", &layout.contract_source("Foo")),
+                    ("header", &layout.contract_header("Foo")),
+                ],
+            )
+            .unwrap();
+        let manifest = layout.read_manifest().unwrap();
+        assert_eq!(manifest.contracts.len(), 1);
+        assert_eq!(manifest.contracts["Foo"]["abi"], "fuzz/abi/Foo.abi");
+        assert_eq!(
+            manifest.contracts["Foo"]["header"],
+            "include/eEVM/evm2cpp/contracts/Foo.h"
+        );
+
+        // nothing is recorded for an eEVM checkout
+        let eevm = OutputLayout::eevm(&dir.join("eEVM"));
+        eevm.record("Bar", &[("abi", &eevm.abi("Bar"))]).unwrap();
+        assert!(!dir.join("eEVM").exists());
+    }
+
+    #[test]
+    fn contracts_header_regeneration() {
+        let dir = TestDir::new("contracts_header");
+        let layout = OutputLayout::out_dir(dir.path()).unwrap();
+        // contracts.h of an eEVM checkout with a hand-written include and a duplicated include of
+        // a contract translated by an older evm2cpp
+        std::fs::write(
+            layout.contracts_header(),
+            "#pragma once\n#include \"my/handwritten.h\"\n#include \"eEVM/evm2cpp/contracts/Old.h\"\n#include \"eEVM/evm2cpp/contracts/Old.h\"\n",
+        )
+        .unwrap();
+        std::fs::write(layout.contract_header("Old"), "").unwrap();
+        for contract_id in ["Foo", "Bar", "Foo"].iter() {
+            std::fs::write(layout.contract_header(contract_id), "").unwrap();
+            layout.update_contracts_header().unwrap();
+        }
+        let header = std::fs::read_to_string(layout.contracts_header()).unwrap();
+        assert_eq!(
+            header,
+            "// auto-generated by evm2cpp - the contract includes are regenerated on every translation
+#pragma once
+#include \"my/handwritten.h\"
+#include \"eEVM/evm2cpp/contracts/Bar.h\"
+#include \"eEVM/evm2cpp/contracts/Foo.h\"
+#include \"eEVM/evm2cpp/contracts/Old.h\"
+"
+        );
+
+        // regenerating again does not change anything
+        layout.update_contracts_header().unwrap();
+        assert_eq!(
+            std::fs::read_to_string(layout.contracts_header()).unwrap(),
+            header
+        );
+    }
+
+    #[test]
+    fn remove_contract() {
+        let dir = TestDir::new("remove_contract");
+        let layout = OutputLayout::out_dir(dir.path()).unwrap();
+        for contract_id in ["Foo", "Bar"].iter() {
+            let files = [
+                ("source", layout.contract_source(contract_id)),
+                ("header", layout.contract_header(contract_id)),
+                ("abi", layout.abi(contract_id)),
+            ];
+            for (_, path) in files.iter() {
+                std::fs::write(path, "").unwrap();
+            }
+            let files: Vec<(&str, &Path)> = files.iter().map(|(k, p)| (*k, p.as_path())).collect();
+            layout.record(contract_id, &files).unwrap();
+        }
+        layout.update_contracts_header().unwrap();
+
+        let removed = layout.remove("Foo").unwrap();
+        assert_eq!(
+            removed,
+            vec![
+                layout.contract_source("Foo"),
+                layout.contract_header("Foo"),
+                layout.abi("Foo"),
+            ]
+        );
+        assert!(removed.iter().all(|p| !p.exists()));
+        assert!(layout.contract_source("Bar").exists());
+
+        let manifest = layout.read_manifest().unwrap();
+        assert_eq!(
+            manifest.contracts.keys().collect::<Vec<_>>(),
+            vec![&"Bar".to_string()]
+        );
+        let header = std::fs::read_to_string(layout.contracts_header()).unwrap();
+        assert!(!header.contains("Foo.h"), "{}", header);
+        assert!(header.contains("#include \"eEVM/evm2cpp/contracts/Bar.h\"\n"));
+
+        // nothing left to remove
+        assert!(layout.remove("Foo").is_err());
+    }
+
+    #[test]
+    fn build_fragments() {
+        let tmp = TestDir::new("build_fragments");
+        let dir = tmp.path();
+        let layout = OutputLayout::out_dir(dir).unwrap();
+        let files = [
+            ("source", layout.contract_source("Bar")),
+            ("header", layout.contract_header("Bar")),
+            ("abi", layout.abi("Bar")),
+            ("dict", layout.dict("Bar")),
+        ];
+        std::fs::write(layout.contract_header("Bar"), "").unwrap();
+        let files: Vec<(&str, &Path)> = files.iter().map(|(k, p)| (*k, p.as_path())).collect();
+
+        // CMake: paths relative to the fragment, appended to the lists of all contracts
+        let cmake = layout
+            .write_build_fragment(BuildFragment::CMake, "Bar", &files)
+            .unwrap();
+        assert_eq!(cmake, dir.join("contracts/Bar.cmake"));
+        assert_eq!(
+            std::fs::read_to_string(cmake).unwrap(),
+            "# auto-generated by evm2cpp for contract Bar
+list(APPEND EVM2CPP_CONTRACTS \"Bar\")
+list(APPEND EVM2CPP_SOURCES \"${CMAKE_CURRENT_LIST_DIR}/../contracts/Bar.cpp\")
+list(APPEND EVM2CPP_HEADERS \"${CMAKE_CURRENT_LIST_DIR}/../include/eEVM/evm2cpp/contracts/Bar.h\")
+set(EVM2CPP_Bar_ABI \"${CMAKE_CURRENT_LIST_DIR}/../fuzz/abi/Bar.abi\")
+set(EVM2CPP_Bar_DICT \"${CMAKE_CURRENT_LIST_DIR}/../fuzz/dict/Bar.dict\")
+"
+        );
+        let index = std::fs::read_to_string(dir.join(CMAKE_INDEX_FILE)).unwrap();
+        assert!(index.ends_with("include(\"${CMAKE_CURRENT_LIST_DIR}/Bar.cmake\")\n"));
+
+        // JSON: paths relative to the (absolute) output directory
+        let json = layout
+            .write_build_fragment(BuildFragment::Json, "Bar", &files)
+            .unwrap();
+        assert_eq!(json, dir.join("contracts/Bar.build.json"));
+        let json: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(json).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "contract": "Bar",
+                "directory": dir.canonicalize().unwrap(),
+                "sources": ["contracts/Bar.cpp"],
+                "headers": ["include/eEVM/evm2cpp/contracts/Bar.h"],
+                "abi": "fuzz/abi/Bar.abi",
+                "dict": "fuzz/dict/Bar.dict",
+            })
+        );
+    }
+}
@@ -0,0 +1,77 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// Number of worker threads used if `--jobs` is not given.
+pub fn default_jobs() -> usize {
+    std::thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Applies `f` to all jobs on (at most) `threads` worker threads. The workers pick the next job
+/// from a shared counter, but the results are returned in the order of the jobs, such that the
+/// output does not depend on the scheduling.
+pub fn map_parallel<T, R, F>(jobs: &[T], threads: usize, f: F) -> Vec<R>
+where
+    T: Sync,
+    R: Send,
+    F: Fn(&T) -> R + Sync,
+{
+    let threads = threads.max(1).min(jobs.len());
+    if threads <= 1 {
+        return jobs.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<R>>> = Mutex::new(jobs.iter().map(|_| None).collect());
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| loop {
+                let idx = next.fetch_add(1, Ordering::Relaxed);
+                if idx >= jobs.len() {
+                    break;
+                }
+                let result = f(&jobs[idx]);
+                results.lock().unwrap()[idx] = Some(result);
+            });
+        }
+    });
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .map(|r| r.expect("every job is run exactly once"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_in_job_order() {
+        let jobs: Vec<u64> = (0..100).collect();
+        let results = map_parallel(&jobs, 8, |j| {
+            // finish later jobs first
+            std::thread::sleep(std::time::Duration::from_micros(100 - j));
+            j * 2
+        });
+        assert_eq!(results, jobs.iter().map(|j| j * 2).collect::<Vec<_>>());
+        assert_eq!(map_parallel(&jobs[..0], 4, |j| *j), Vec::<u64>::new());
+        assert_eq!(map_parallel(&jobs[..3], 1, |j| *j), vec![0, 1, 2]);
+    }
+}
@@ -14,7 +14,10 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <http://www.gnu.org/licenses/>.
 
+use crate::analysis::IInstruction;
+use crate::combinedjson::GeneratedSource;
 use anyhow::Context;
+use std::collections::{BTreeMap, BTreeSet};
 use std::rc::Rc;
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -36,141 +39,858 @@ impl JumpType {
     }
 }
 
+/// Errors that can occur while decompressing a sourcemap and resolving it against the source
+/// files.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SourceMapError {
+    /// The first entry must at least specify the byte offset, length and file index, since there
+    /// is no previous entry to take the values from.
+    IncompleteFirstEntry { fields: usize },
+    /// A field of an entry could not be parsed.
+    InvalidField {
+        entry: usize,
+        field: &'static str,
+        value: String,
+    },
+    /// The byte range of the entry is not within the source file.
+    RangeOutOfBounds {
+        entry: usize,
+        byte_offset: usize,
+        length: usize,
+        file_length: usize,
+    },
+}
+
+impl std::fmt::Display for SourceMapError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceMapError::IncompleteFirstEntry { fields } => write!(
+                f,
+                "invalid sourcemap - first entry must contain at least offset, length and file index (got {} fields)",
+                fields
+            ),
+            SourceMapError::InvalidField { entry, field, value } => write!(
+                f,
+                "invalid sourcemap - failed to parse entry {}: unparsable {} = {:?}",
+                entry, field, value
+            ),
+            SourceMapError::RangeOutOfBounds {
+                entry,
+                byte_offset,
+                length,
+                file_length,
+            } => write!(
+                f,
+                "invalid sourcemap - entry {} refers to bytes {}..{}, but source file has only {} bytes",
+                entry,
+                byte_offset,
+                byte_offset + length,
+                file_length
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SourceMapError {}
+
+/// A decompressed sourcemap entry, which is not yet resolved to a source file.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RawSourceMapEntry {
+    pub byte_offset: usize,
+    pub length: usize,
+    /// index into the source list; `-1` means that the instruction is not mapped to any source
+    pub file_index: i32,
+    pub jump_type: JumpType,
+    pub modifier_depth: usize,
+}
+
+/// A source file that is referred to by a sourcemap.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SourceFile {
+    pub name: Rc<String>,
+    pub contents: String,
+    /// whether the source was generated by the compiler (e.g., the Yul utility functions for ABI
+    /// decoding or checked arithmetic emitted by newer solc versions) and does not exist on disk
+    pub generated: bool,
+}
+
+/// The source files of a sourcemap by their source index. solc numbers the files of the source
+/// list first and continues with the generated sources.
+pub type SourceFiles = BTreeMap<i32, SourceFile>;
+
+/// A source range in terms of lines and columns. Lines and columns are 1-based and counted in
+/// bytes; the end position points just past the last byte of the range.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct SourceLocation {
+    pub file: Rc<String>,
+    pub start_line: usize,
+    pub start_col: usize,
+    pub end_line: usize,
+    pub end_col: usize,
+    /// whether the location refers to a compiler-generated source
+    pub generated: bool,
+}
+
+impl SourceLocation {
+    fn from_byte_range(file: &SourceFile, range: std::ops::Range<usize>) -> Self {
+        let contents = file.contents.as_bytes();
+        let position = |offset: usize| -> (usize, usize) {
+            let before = &contents[..offset];
+            let line = 1 + before.iter().filter(|&&c| c == b'\n').count();
+            let line_start = before
+                .iter()
+                .rposition(|&c| c == b'\n')
+                .map(|i| i + 1)
+                .unwrap_or(0);
+            (line, offset - line_start + 1)
+        };
+        let (start_line, start_col) = position(range.start);
+        let (end_line, end_col) = position(range.end);
+        SourceLocation {
+            file: file.name.clone(),
+            start_line,
+            start_col,
+            end_line,
+            end_col,
+            generated: file.generated,
+        }
+    }
+}
+
+impl std::fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}-{}:{}",
+            self.file, self.start_line, self.start_col, self.end_line, self.end_col
+        )
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct SourceMapEntry {
-    byte_offset: usize,
-    length: usize,
-    file_index: i32,
+    pub byte_offset: usize,
+    pub length: usize,
+    /// index into the source list; `-1` means that the instruction is not mapped to any source
+    pub file_index: i32,
     pub jump_type: JumpType,
     pub modifier_depth: usize,
-    pub line: Rc<String>,
-    pub line_number: usize,
+    /// source code the entry refers to; empty if the entry is not mapped to any source
+    pub source: Rc<String>,
+    /// location of the source range; `None` if the instruction does not correspond to any
+    /// source (e.g., compiler-generated code).
+    pub location: Option<SourceLocation>,
 }
 
 pub type SourceMap = Vec<SourceMapEntry>;
 
+/// Returns the sourcemap entry of the given instruction. Instructions that are not covered by the
+/// sourcemap (e.g., the metadata trailing the code, which is also decoded as instructions) have
+/// no entry.
+pub fn instruction_entry<'a>(
+    sourcemap: &'a SourceMap,
+    inst: &IInstruction,
+) -> Option<&'a SourceMapEntry> {
+    sourcemap.get(inst.global_idx)
+}
+
+/// Returns the source location of the given instruction, if it is mapped to any source.
+pub fn instruction_location<'a>(
+    sourcemap: &'a SourceMap,
+    inst: &IInstruction,
+) -> Option<&'a SourceLocation> {
+    instruction_entry(sourcemap, inst).and_then(|e| e.location.as_ref())
+}
+
+fn parse_field<T: std::str::FromStr>(
+    entry: usize,
+    field: &'static str,
+    value: &str,
+) -> Result<T, SourceMapError> {
+    value.parse().map_err(|_| SourceMapError::InvalidField {
+        entry,
+        field,
+        value: value.to_string(),
+    })
+}
+
+/// Parses the byte offset or length of an entry. solc emits `-1` for both in entries that are not
+/// mapped to any source (e.g., `-1:-1:-1`), which is treated as `0`.
+fn parse_range_field(
+    entry: usize,
+    field: &'static str,
+    value: &str,
+) -> Result<usize, SourceMapError> {
+    if value == "-1" {
+        Ok(0)
+    } else {
+        parse_field(entry, field, value)
+    }
+}
+
+/// Decompresses a solidity sourcemap. The format is described here:
+/// https://docs.soliditylang.org/en/v0.8.0/internals/source_mappings.html
+///
+/// Every entry is of the form `s:l:f:j:m`. Empty fields take the value of the previous entry and
+/// if a `:` is missing, all following fields are considered empty. Older solc versions do not
+/// emit the modifier depth `m` (or even the jump type `j`), so these default to `0` (and `-`) if
+/// they are missing from the first entry.
+pub fn decompress_source_map(
+    source_map_string: &str,
+) -> Result<Vec<RawSourceMapEntry>, SourceMapError> {
+    let mut entries: Vec<RawSourceMapEntry> = Vec::new();
+    if source_map_string.trim().is_empty() {
+        return Ok(entries);
+    }
+
+    for (idx, entry) in source_map_string.trim().split(';').enumerate() {
+        let fields: Vec<&str> = entry.split(':').collect();
+        let field = |i: usize| -> Option<&str> { fields.get(i).cloned().filter(|f| !f.is_empty()) };
+
+        let prev = if let Some(prev) = entries.last() {
+            *prev
+        } else {
+            if (0..3).any(|i| field(i).is_none()) {
+                return Err(SourceMapError::IncompleteFirstEntry {
+                    fields: fields.iter().filter(|f| !f.is_empty()).count(),
+                });
+            }
+            RawSourceMapEntry {
+                byte_offset: 0,
+                length: 0,
+                file_index: -1,
+                jump_type: JumpType::Jump,
+                modifier_depth: 0,
+            }
+        };
+
+        let byte_offset = match field(0) {
+            Some(f) => parse_range_field(idx, "byte_offset", f)?,
+            None => prev.byte_offset,
+        };
+        let length = match field(1) {
+            Some(f) => parse_range_field(idx, "length", f)?,
+            None => prev.length,
+        };
+        let file_index = match field(2) {
+            Some(f) => parse_field(idx, "file_index", f)?,
+            None => prev.file_index,
+        };
+        let jump_type = match field(3) {
+            Some(f) => {
+                let mut chars = f.chars();
+                match (chars.next().and_then(JumpType::parse), chars.next()) {
+                    (Some(j), None) => j,
+                    _ => {
+                        return Err(SourceMapError::InvalidField {
+                            entry: idx,
+                            field: "jump_type",
+                            value: f.to_string(),
+                        })
+                    }
+                }
+            }
+            None => prev.jump_type,
+        };
+        let modifier_depth = match field(4) {
+            Some(f) => parse_field(idx, "modifier_depth", f)?,
+            None => prev.modifier_depth,
+        };
+
+        entries.push(RawSourceMapEntry {
+            byte_offset,
+            length,
+            file_index,
+            jump_type,
+            modifier_depth,
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Resolves the decompressed entries against the contents of the source files. Entries that refer
+/// to a source index that is not known (e.g., compiler-generated sources of solc output without
+/// `generatedSources`) are treated like `-1`, i.e., as not mapped to any source.
+pub fn resolve_source_map(
+    raw_entries: &[RawSourceMapEntry],
+    source_files: &SourceFiles,
+) -> Result<SourceMap, SourceMapError> {
+    let no_source = Rc::new(String::new());
+    let mut entries = Vec::with_capacity(raw_entries.len());
+    let mut unknown_indices = BTreeSet::new();
+    for (idx, raw) in raw_entries.iter().enumerate() {
+        let file = source_files.get(&raw.file_index);
+        if file.is_none() && raw.file_index != -1 {
+            unknown_indices.insert(raw.file_index);
+        }
+        let file = if let Some(file) = file {
+            file
+        } else {
+            entries.push(SourceMapEntry {
+                byte_offset: raw.byte_offset,
+                length: raw.length,
+                file_index: raw.file_index,
+                jump_type: raw.jump_type,
+                modifier_depth: raw.modifier_depth,
+                source: no_source.clone(),
+                location: None,
+            });
+            continue;
+        };
+
+        let bytes = file.contents.as_bytes();
+        let end = raw.byte_offset.checked_add(raw.length);
+        let range = match end {
+            Some(end) if end <= bytes.len() => raw.byte_offset..end,
+            _ => {
+                return Err(SourceMapError::RangeOutOfBounds {
+                    entry: idx,
+                    byte_offset: raw.byte_offset,
+                    length: raw.length,
+                    file_length: bytes.len(),
+                })
+            }
+        };
+
+        let location = SourceLocation::from_byte_range(file, range.clone());
+        // the byte range might split a multi-byte character, so we convert lossy
+        let source = String::from_utf8_lossy(&bytes[range]).into_owned();
+
+        entries.push(SourceMapEntry {
+            byte_offset: raw.byte_offset,
+            length: raw.length,
+            file_index: raw.file_index,
+            jump_type: raw.jump_type,
+            modifier_depth: raw.modifier_depth,
+            source: Rc::new(source),
+            location: Some(location),
+        });
+    }
+
+    if !unknown_indices.is_empty() {
+        let indices: Vec<String> = unknown_indices.iter().map(|i| i.to_string()).collect();
+        println!(
+            "[WARNING] sourcemap refers to unknown source index(es) {} (compiler-generated sources?) - these instructions are treated as not mapped to any source",
+            indices.join(", ")
+        );
+    }
+
+    Ok(entries)
+}
+
 pub fn parse_source_map_file(
     source_map_path: &str,
     source_files: &[&str],
+    generated_sources: &[GeneratedSource],
 ) -> anyhow::Result<SourceMap> {
     // read input files
     let source_map_string = std::fs::read_to_string(source_map_path)
         .with_context(|| format!("failed to read source map file: {}", source_map_path))?;
-    parse_source_map(&source_map_string, source_files)
+    parse_source_map(&source_map_string, source_files, generated_sources)
 }
 
+/// Parses the sourcemap and resolves it against the given source files (in the order of the
+/// source list) and the compiler-generated sources.
 pub fn parse_source_map(
     source_map_string: &str,
     source_files: &[&str],
+    generated_sources: &[GeneratedSource],
 ) -> anyhow::Result<SourceMap> {
-    let mut file_contents: Vec<String> = Vec::new();
+    let mut sources: Vec<(String, String)> = Vec::new();
     for sf in source_files.iter() {
-        file_contents.push(
-            std::fs::read_to_string(sf)
-                .with_context(|| format!("failed to read solidity source file: {}", sf))?,
+        let contents = std::fs::read_to_string(sf)
+            .with_context(|| format!("failed to read solidity source file: {}", sf))?;
+        sources.push((sf.to_string(), contents));
+    }
+    parse_source_map_from_contents(source_map_string, &sources, generated_sources)
+}
+
+/// Like `parse_source_map`, but the contents of the source files are already in memory, e.g.,
+/// because they are embedded in a build-info file. The sources are given as `(name, contents)`.
+pub fn parse_source_map_from_contents(
+    source_map_string: &str,
+    sources: &[(String, String)],
+    generated_sources: &[GeneratedSource],
+) -> anyhow::Result<SourceMap> {
+    let mut files = SourceFiles::new();
+    for (idx, (name, contents)) in sources.iter().enumerate() {
+        files.insert(
+            idx as i32,
+            SourceFile {
+                name: Rc::new(name.clone()),
+                contents: contents.clone(),
+                generated: false,
+            },
+        );
+    }
+    for gs in generated_sources.iter() {
+        files.insert(
+            gs.id,
+            SourceFile {
+                name: Rc::new(gs.name.clone()),
+                contents: gs.contents.clone(),
+                generated: true,
+            },
         );
     }
-    let mut entries: Vec<SourceMapEntry> = Vec::new();
 
     // the sourcemap format is described here:
     // https://docs.soliditylang.org/en/v0.8.0/internals/source_mappings.html?highlight=source%20map#source-mappings
     // and for older versions:
     // https://docs.soliditylang.org/en/v0.5.3/miscellaneous.html?highlight=source%20map#source-mappings
+    let raw_entries = decompress_source_map(source_map_string)?;
+    Ok(resolve_source_map(&raw_entries, &files)?)
+}
 
-    for (idx, entry) in source_map_string.split(';').into_iter().enumerate() {
-        if entry.is_empty() {
-            entries.push(entries[entries.len() - 1].clone());
-        } else {
-            let fields: Vec<&str> = entry.split(':').collect();
-            if entries.is_empty() && fields.len() < 4 {
-                bail!("Invalid sourcemap - First entry of sourcemap must contain all fields (got {})!", fields.len());
-            }
-            let byte_offset: usize = if !fields.is_empty() && !fields[0].is_empty() {
-                fields[0].parse().with_context(|| {
-                    format!(
-                        "Failed to parse entry {} unparsable byte_offset = {:?}",
-                        idx, fields[0]
-                    )
-                })?
-            } else {
-                entries[idx - 2].byte_offset
-            };
-            let length: usize = if fields.len() > 1 && fields[1].len() > 0 {
-                fields[1].parse().with_context(|| {
-                    format!(
-                        "Failed to parse entry {} unparsable length = {:?}",
-                        idx, fields[1]
-                    )
-                })?
-            } else {
-                entries[idx - 2].length
-            };
-            let file_index: i32 = if fields.len() > 2 && fields[2].len() > 0 {
-                fields[2].parse().with_context(|| {
-                    format!(
-                        "Failed to parse entry {} unparsable file_index = {:?}",
-                        idx, fields[2]
-                    )
-                })?
-            } else {
-                entries[idx - 2].file_index
-            };
-            let jump_type = if fields.len() > 3 && fields[3].len() > 0 {
-                JumpType::parse(fields[3].chars().next().unwrap()).with_context(|| {
-                    format!(
-                        "Failed to parse entry {} unparsable jump_type = {:?}",
-                        idx, fields[3]
-                    )
-                })?
-            } else {
-                entries[idx - 2].jump_type
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sources(files: &[(&str, &str)]) -> SourceFiles {
+        files
+            .iter()
+            .enumerate()
+            .map(|(idx, (name, contents))| {
+                let file = SourceFile {
+                    name: Rc::new(name.to_string()),
+                    contents: contents.to_string(),
+                    generated: false,
+                };
+                (idx as i32, file)
+            })
+            .collect()
+    }
+
+    fn raw(byte_offset: usize, length: usize, file_index: i32) -> RawSourceMapEntry {
+        RawSourceMapEntry {
+            byte_offset,
+            length,
+            file_index,
+            jump_type: JumpType::Jump,
+            modifier_depth: 0,
+        }
+    }
+
+    /// Compresses the entries according to the sourcemap specification, i.e., the inverse of
+    /// `decompress_source_map`.
+    fn compress(entries: &[RawSourceMapEntry]) -> String {
+        let mut out: Vec<String> = Vec::new();
+        let mut prev: Option<RawSourceMapEntry> = None;
+        for e in entries.iter() {
+            let jump = match e.jump_type {
+                JumpType::Call => "i",
+                JumpType::Return => "o",
+                JumpType::Jump => "-",
             };
-            let modifier_depth: usize = if fields.len() > 4 && fields[4].len() > 0 {
-                fields[4].parse().with_context(|| {
-                    format!(
-                        "Failed to parse entry {} unparsable modifier_depth = {:?}",
-                        idx, fields[4]
-                    )
-                })?
-            } else {
-                // NOTE: in older solidity versions, there is no modifier depth... so our normal
-                // parsing routine fails here. We just return 0 for the first entry and all other
-                // entries will then copy the value from the first field, which is 0...
-                if !entries.is_empty() {
-                    entries[idx - 2].modifier_depth
-                } else {
-                    0
+            let mut fields = vec![
+                e.byte_offset.to_string(),
+                e.length.to_string(),
+                e.file_index.to_string(),
+                jump.to_string(),
+                e.modifier_depth.to_string(),
+            ];
+            if let Some(p) = prev {
+                let same = [
+                    p.byte_offset == e.byte_offset,
+                    p.length == e.length,
+                    p.file_index == e.file_index,
+                    p.jump_type == e.jump_type,
+                    p.modifier_depth == e.modifier_depth,
+                ];
+                for (f, same) in fields.iter_mut().zip(same.iter()) {
+                    if *same {
+                        f.clear();
+                    }
                 }
-            };
+                while fields.last().is_some_and(|f| f.is_empty()) {
+                    fields.pop();
+                }
+            }
+            out.push(fields.join(":"));
+            prev = Some(*e);
+        }
+        out.join(";")
+    }
 
-            let u_file_index = if file_index < 0 {
-                (file_contents.len() as i32 + file_index) as usize
-            } else {
-                file_index as usize
-            };
-            let mut fi = file_contents[u_file_index].as_bytes().iter();
-            // count newlines up to byte offset
-            let lineno = 1 + (&mut fi).take(byte_offset).filter(|&&c| c == b'\n').count();
-            // take length bytes
-            let line_bytes = fi.take(length).cloned().collect();
+    #[test]
+    fn decompress_spec_example() {
+        // example from the solidity documentation
+        let expected = vec![
+            raw(1, 2, 1),
+            raw(1, 9, 1),
+            raw(2, 1, 2),
+            raw(2, 1, 2),
+            raw(2, 1, 2),
+        ];
+        assert_eq!(
+            decompress_source_map("1:2:1;1:9:1;2:1:2;2:1:2;2:1:2").unwrap(),
+            expected
+        );
+        assert_eq!(decompress_source_map("1:2:1;:9;2:1:2;;").unwrap(), expected);
+    }
 
-            let line = String::from_utf8(line_bytes)?;
+    #[test]
+    fn decompress_takes_previous_entry() {
+        let entries = decompress_source_map("0:10:0:-:0;5;:3:1:i;;::::2;:::o").unwrap();
+        assert_eq!(entries[1], raw(5, 10, 0));
+        assert_eq!(entries[2].byte_offset, 5);
+        assert_eq!(entries[2].length, 3);
+        assert_eq!(entries[2].file_index, 1);
+        assert_eq!(entries[2].jump_type, JumpType::Call);
+        assert_eq!(entries[3], entries[2]);
+        assert_eq!(entries[4].modifier_depth, 2);
+        assert_eq!(entries[4].jump_type, JumpType::Call);
+        assert_eq!(entries[5].jump_type, JumpType::Return);
+        assert_eq!(entries[5].modifier_depth, 2);
+    }
 
-            let sm_entry = SourceMapEntry {
-                byte_offset,
-                length,
-                file_index,
-                jump_type,
-                modifier_depth,
-                line: Rc::new(line),
-                line_number: lineno,
-            };
+    #[test]
+    fn decompress_old_format() {
+        // solc 0.4.x does not emit the modifier depth
+        let entries = decompress_source_map("93:6800:0:-;;;2057:209;;").unwrap();
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries[5].byte_offset, 2057);
+        assert_eq!(entries[5].length, 209);
+        assert_eq!(entries[5].modifier_depth, 0);
+    }
+
+    #[test]
+    fn decompress_unmapped_entries() {
+        // solc emits `-1` offsets/lengths for instructions without source
+        let entries = decompress_source_map("1:2:0:-:0;-1:-1:-1;;3:4:0").unwrap();
+        assert_eq!(entries[1], raw(0, 0, -1));
+        assert_eq!(entries[2], raw(0, 0, -1));
+        assert_eq!(entries[3], raw(3, 4, 0));
+    }
+
+    #[test]
+    fn decompress_errors() {
+        assert_eq!(
+            decompress_source_map(";1:2:0"),
+            Err(SourceMapError::IncompleteFirstEntry { fields: 0 })
+        );
+        assert_eq!(
+            decompress_source_map("1:2"),
+            Err(SourceMapError::IncompleteFirstEntry { fields: 2 })
+        );
+        assert_eq!(
+            decompress_source_map("1:2:0;x"),
+            Err(SourceMapError::InvalidField {
+                entry: 1,
+                field: "byte_offset",
+                value: "x".to_string()
+            })
+        );
+        assert_eq!(
+            decompress_source_map("1:2:0:x"),
+            Err(SourceMapError::InvalidField {
+                entry: 0,
+                field: "jump_type",
+                value: "x".to_string()
+            })
+        );
+        assert_eq!(decompress_source_map("").unwrap(), vec![]);
+    }
 
-            //println!("{:?}", sm_entry);
+    #[test]
+    fn resolve_errors_and_no_source() {
+        let files = sources(&[("a.sol", "ab\ncd\n")]);
+        let sm = resolve_source_map(&[raw(3, 2, 0), raw(0, 0, -1)], &files).unwrap();
+        assert_eq!(sm[0].source.as_str(), "cd");
+        assert_eq!(
+            sm[0].location.as_ref().unwrap().to_string(),
+            "a.sol:2:1-2:3".to_string()
+        );
+        assert_eq!(sm[1].location, None);
+
+        // e.g., compiler-generated sources without `generatedSources` in the compiler output
+        let sm = resolve_source_map(&[raw(0, 1, 1), raw(3, 2, 0)], &files).unwrap();
+        assert_eq!(sm[0].location, None);
+        assert_eq!(sm[0].source.as_str(), "");
+        assert_eq!(sm[1].source.as_str(), "cd");
+        assert_eq!(
+            resolve_source_map(&[raw(0, 1, 0), raw(5, 2, 0)], &files),
+            Err(SourceMapError::RangeOutOfBounds {
+                entry: 1,
+                byte_offset: 5,
+                length: 2,
+                file_length: 6
+            })
+        );
+    }
+
+    #[test]
+    fn multiline_location() {
+        let files = sources(&[("a.sol", "contract A {\n    function f() {\n    }\n}\n")]);
+        // the function `f` from `function` up to the closing brace
+        let sm = resolve_source_map(&[raw(17, 20, 0), raw(0, 0, 0)], &files).unwrap();
+        let loc = sm[0].location.as_ref().unwrap();
+        assert_eq!(
+            (loc.start_line, loc.start_col, loc.end_line, loc.end_col),
+            (2, 5, 3, 6)
+        );
+        let loc = sm[1].location.as_ref().unwrap();
+        assert_eq!(
+            (loc.start_line, loc.start_col, loc.end_line, loc.end_col),
+            (1, 1, 1, 1)
+        );
+    }
+
+    #[test]
+    fn generated_sources() {
+        let mut files = sources(&[("a.sol", "contract A {}\n")]);
+        files.insert(
+            1,
+            SourceFile {
+                name: Rc::new("#utility.yul".to_string()),
+                contents: "{\n    function abi_decode() {}\n}\n".to_string(),
+                generated: true,
+            },
+        );
+        let sm = resolve_source_map(&[raw(0, 13, 0), raw(6, 24, 1)], &files).unwrap();
+        assert!(!sm[0].location.as_ref().unwrap().generated);
+        let loc = sm[1].location.as_ref().unwrap();
+        assert!(loc.generated);
+        assert_eq!(loc.to_string(), "#utility.yul:2:5-2:29");
+        assert_eq!(sm[1].source.as_str(), "function abi_decode() {}");
+    }
+
+    /// Decodes the runtime sourcemap of real solc output (see `contracts/fixtures`) and checks the
+    /// resolved locations against the source file.
+    #[test]
+    fn solc_fixture() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("contracts/fixtures");
+        let (contract, source_path) =
+            crate::combinedjson::read_foundry_artifact(&dir.join("verifier.json")).unwrap();
+        assert_eq!(source_path.as_deref(), Some("src/verifier.sol"));
+
+        // solc maps every instruction up to the INVALID that separates the code from the metadata
+        let raw_entries = decompress_source_map(&contract.srcmap_runtime).unwrap();
+        let bytecode = hexutil::read_hex(&contract.bin_runtime).unwrap();
+        let code_end = crate::disasm::metadata_range(&bytecode).unwrap().start;
+        let program = crate::analysis::Program::new(&bytecode);
+        let instructions: Vec<_> = program
+            .basic_blocks
+            .iter()
+            .flat_map(|bb| bb.instructions.iter())
+            .filter(|i| i.address < code_end)
+            .collect();
+        assert_eq!(
+            instructions.last().unwrap().opcode,
+            Ok(crate::instructions::Instruction::INVALID)
+        );
+        assert_eq!(raw_entries.len(), instructions.len() - 1);
+
+        // index 1 is a compiler-generated source, which is not part of the artifact
+        let sm = parse_source_map(
+            &contract.srcmap_runtime,
+            &[dir.join("verifier.sol").to_str().unwrap()],
+            &[],
+        )
+        .unwrap();
+        assert_eq!(sm.len(), raw_entries.len());
+        let unmapped = sm.iter().filter(|e| e.location.is_none()).count();
+        assert_eq!(
+            unmapped,
+            raw_entries.iter().filter(|e| e.file_index != 0).count()
+        );
+        assert!(unmapped > 0 && unmapped < sm.len());
+
+        let line_and_source = |idx: usize| {
+            let e = &sm[idx];
+            (
+                e.location.as_ref().unwrap().start_line,
+                e.jump_type,
+                e.source.lines().next().unwrap().trim().to_string(),
+            )
+        };
+        assert_eq!(
+            line_and_source(37),
+            (20, JumpType::Call, "function verify(".to_string())
+        );
+        assert_eq!(
+            line_and_source(65),
+            (25, JumpType::Call, "Verifier.verify(".to_string())
+        );
+        assert_eq!(
+            line_and_source(119),
+            (
+                60,
+                JumpType::Call,
+                "require(input.length + 1 == vk.IC.length, \"verifier-bad-input\")".to_string()
+            )
+        );
+    }
+
+    /// Decodes both sourcemaps of every contract in the solc output of all example contracts (see
+    /// `contracts/fixtures`). The code of a contract starts with its definition and every public
+    /// function is mapped to its definition, which must resolve to the lines they are declared on.
+    #[test]
+    #[ignore = "requires the solc output of all example contracts in contracts/fixtures (make -C contracts fixtures)"]
+    fn example_contract_fixtures() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("contracts");
+        let mut paths: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "sol"))
+            .collect();
+        paths.sort();
+
+        // the 1-based line of the first line of the source that starts with the given declaration
+        let declaration_line = |contents: &str, declaration: &str| {
+            contents.lines().position(|l| {
+                l.trim_start()
+                    .strip_prefix(declaration)
+                    .is_some_and(|rest| {
+                        !rest.starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+                    })
+            })
+        };
+
+        for path in paths.iter() {
+            let stem = path.file_stem().unwrap().to_str().unwrap();
+            let fixture = dir.join(format!("fixtures/{}.combined.json", stem));
+            let combined = crate::combinedjson::read_combined_from_file(fixture.to_str().unwrap())
+                .unwrap_or_else(|e| panic!("no solc output for {}: {:?}", path.display(), e));
+            let files: Vec<String> = combined
+                .source_list
+                .iter()
+                .map(|s| dir.join(s).to_str().unwrap().to_string())
+                .collect();
+            let files: Vec<&str> = files.iter().map(|s| s.as_str()).collect();
+            let contents = std::fs::read_to_string(path).unwrap();
+
+            for (key, contract) in combined.contracts.iter() {
+                let name = key.rsplit(':').next().unwrap();
+                for (srcmap, code) in [
+                    (&contract.srcmap, &contract.bin),
+                    (&contract.srcmap_runtime, &contract.bin_runtime),
+                ] {
+                    // interfaces and abstract contracts have no code
+                    if code.trim().is_empty() {
+                        continue;
+                    }
+                    let what = format!("{} of {}", key, fixture.display());
+                    let raw_entries =
+                        decompress_source_map(srcmap).unwrap_or_else(|e| panic!("{}: {}", what, e));
+                    let bytecode = hexutil::read_hex(code.trim()).unwrap();
+                    let instructions = crate::analysis::Program::new(&bytecode)
+                        .basic_blocks
+                        .iter()
+                        .map(|bb| bb.instructions.len())
+                        .sum::<usize>();
+                    assert!(
+                        !raw_entries.is_empty() && raw_entries.len() <= instructions,
+                        "{}",
+                        what
+                    );
+                    let sm = parse_source_map(srcmap, &files, &[])
+                        .unwrap_or_else(|e| panic!("{}: {:?}", what, e));
+                    assert_eq!(sm.len(), raw_entries.len(), "{}", what);
+
+                    let definition = ["contract", "library"]
+                        .iter()
+                        .map(|kind| format!("{} {}", kind, name))
+                        .find(|d| sm[0].source.starts_with(d.as_str()))
+                        .unwrap_or_else(|| panic!("{}: starts with {:?}", what, sm[0].source));
+                    let location = sm[0].location.as_ref().unwrap();
+                    if location.file.as_str() == path.to_str().unwrap() {
+                        assert_eq!(
+                            Some(location.start_line - 1),
+                            declaration_line(&contents, &definition),
+                            "{}",
+                            what
+                        );
+                    }
+                }
 
-            entries.push(sm_entry);
+                // the body of every function defined in the source starts with its definition
+                let abi: serde_json::Value = serde_json::from_str(&contract.abi).unwrap();
+                let sm = parse_source_map(&contract.srcmap_runtime, &files, &[]).unwrap();
+                for function in abi.as_array().unwrap().iter() {
+                    let function_name = match function["name"].as_str() {
+                        Some(n) if function["type"] == "function" => n,
+                        _ => continue,
+                    };
+                    let definition = format!("function {}", function_name);
+                    let line = match declaration_line(&contents, &definition) {
+                        Some(line) => line + 1,
+                        // e.g., getters of public state variables or inherited from other files
+                        None => continue,
+                    };
+                    assert!(
+                        sm.iter().any(|e| e.source.starts_with(&definition)
+                            && e.location.as_ref().is_some_and(|l| l.start_line == line)),
+                        "{} of {}: no entry for {} on line {}",
+                        key,
+                        fixture.display(),
+                        definition,
+                        line
+                    );
+                }
+            }
         }
     }
 
-    Ok(entries)
+    /// Round-trips a sourcemap over every statement-ish line of all example contracts and checks
+    /// that the entries resolve to the right lines.
+    #[test]
+    fn example_contracts() {
+        let dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("contracts");
+        let mut paths: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .filter(|p| p.extension().is_some_and(|e| e == "sol"))
+            .collect();
+        paths.sort();
+        assert!(!paths.is_empty());
+
+        for path in paths.iter() {
+            let contents = std::fs::read_to_string(path).unwrap();
+            let mut entries = Vec::new();
+            let mut expected = Vec::new();
+            let mut offset = 0;
+            for (lineno, line) in contents.split_inclusive('\n').enumerate() {
+                let text = line.trim_end();
+                if !text.trim().is_empty() {
+                    let mut e = raw(offset, text.len(), 0);
+                    e.jump_type = match lineno % 3 {
+                        0 => JumpType::Jump,
+                        1 => JumpType::Call,
+                        _ => JumpType::Return,
+                    };
+                    e.modifier_depth = lineno % 2;
+                    // every instruction is usually mapped multiple times
+                    entries.push(e);
+                    entries.push(e);
+                    expected.push((lineno + 1, text.to_string()));
+                    expected.push((lineno + 1, text.to_string()));
+                    // and some instructions are not mapped at all
+                    if lineno % 7 == 0 {
+                        entries.push(raw(0, 0, -1));
+                        expected.push((0, String::new()));
+                    }
+                }
+                offset += line.len();
+            }
+
+            let compressed = compress(&entries);
+            assert_eq!(
+                decompress_source_map(&compressed).unwrap(),
+                entries,
+                "{}",
+                path.display()
+            );
+            let sm = parse_source_map(&compressed, &[path.to_str().unwrap()], &[]).unwrap();
+            let resolved: Vec<(usize, String)> = sm
+                .iter()
+                .map(|e| {
+                    let line = e.location.as_ref().map(|l| l.start_line).unwrap_or(0);
+                    (line, e.source.to_string())
+                })
+                .collect();
+            assert_eq!(resolved, expected, "{}", path.display());
+        }
+    }
 }
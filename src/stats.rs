@@ -0,0 +1,197 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::analysis::Program;
+use crate::asm::mnemonic;
+use crate::cfg::{build_cfg, EdgeKind};
+use crate::codegen::{dictionary_entries, program_constants};
+use crate::disasm::metadata_range;
+use crate::instructions::Instruction;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+/// Number of opcodes listed in the text output.
+const TOP_OPCODES: usize = 10;
+
+/// Statistics about the runtime code of a contract, as seen by the analysis.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub code_size: usize,
+    pub metadata_size: usize,
+    pub instructions: usize,
+    pub basic_blocks: usize,
+    pub jumpdests: usize,
+    /// JUMP and JUMPI instructions
+    pub jumps: usize,
+    /// jumps with a constant target after constant folding
+    pub resolved_jumps: usize,
+    /// non-PUSH instructions whose result is known after constant folding
+    pub folded_instructions: usize,
+    pub undefined_opcodes: usize,
+    pub external_calls: usize,
+    pub dictionary_entries: usize,
+    /// number of occurrences per mnemonic
+    pub opcodes: BTreeMap<String, usize>,
+}
+
+/// Computes the statistics of an optimized program. Everything after the start of the metadata
+/// is ignored.
+pub fn compute_stats(program: &Program) -> Stats {
+    let metadata = metadata_range(&program.bytecode);
+    let code_end = metadata
+        .as_ref()
+        .map_or(program.bytecode.len(), |r| r.start);
+    let cfg = build_cfg(program);
+
+    let mut stats = Stats {
+        code_size: program.bytecode.len(),
+        metadata_size: metadata.map_or(0, |r| r.len()),
+        basic_blocks: cfg.blocks.len(),
+        resolved_jumps: cfg
+            .edges
+            .iter()
+            .filter(|e| e.kind == EdgeKind::Jump)
+            .count(),
+        dictionary_entries: dictionary_entries(program, program_constants(program).iter()).len(),
+        ..Default::default()
+    };
+    for inst in program
+        .basic_blocks
+        .iter()
+        .flat_map(|bb| bb.instructions.iter())
+        .filter(|inst| inst.address < code_end)
+    {
+        stats.instructions += 1;
+        *stats.opcodes.entry(mnemonic(inst.opcode)).or_insert(0) += 1;
+        match inst.opcode {
+            Ok(i) => {
+                if i == Instruction::JUMPDEST {
+                    stats.jumpdests += 1;
+                }
+                if i.is_jump() {
+                    stats.jumps += 1;
+                }
+                if i.is_call() {
+                    stats.external_calls += 1;
+                }
+                if !i.is_push() && inst.value.is_some() {
+                    stats.folded_instructions += 1;
+                }
+            }
+            Err(_) => stats.undefined_opcodes += 1,
+        }
+    }
+    stats
+}
+
+fn percent(part: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        100.0 * part as f64 / total as f64
+    }
+}
+
+/// Renders the statistics as text with the most frequent opcodes.
+pub fn stats_text(stats: &Stats) -> String {
+    let mut text = format!(
+        "code size:           {} bytes ({} bytes metadata)
+instructions:        {}
+basic blocks:        {}
+jump destinations:   {}
+jumps:               {} ({} resolved, {:.1}%)
+folded instructions: {} ({:.1}%)
+undefined opcodes:   {}
+external calls:      {}
+dictionary entries:  {}
+",
+        stats.code_size,
+        stats.metadata_size,
+        stats.instructions,
+        stats.basic_blocks,
+        stats.jumpdests,
+        stats.jumps,
+        stats.resolved_jumps,
+        percent(stats.resolved_jumps, stats.jumps),
+        stats.folded_instructions,
+        percent(stats.folded_instructions, stats.instructions),
+        stats.undefined_opcodes,
+        stats.external_calls,
+        stats.dictionary_entries,
+    );
+
+    let mut opcodes: Vec<(&String, &usize)> = stats.opcodes.iter().collect();
+    opcodes.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+    text += "most frequent opcodes:\n";
+    for (name, count) in opcodes.into_iter().take(TOP_OPCODES) {
+        text += &format!("  {:<14} {}\n", name, count);
+    }
+    text
+}
+
+/// Renders the statistics as JSON.
+pub fn stats_json(stats: &Stats) -> Value {
+    json!({
+        "code_size": stats.code_size,
+        "metadata_size": stats.metadata_size,
+        "instructions": stats.instructions,
+        "basic_blocks": stats.basic_blocks,
+        "jumpdests": stats.jumpdests,
+        "jumps": stats.jumps,
+        "resolved_jumps": stats.resolved_jumps,
+        "folded_instructions": stats.folded_instructions,
+        "undefined_opcodes": stats.undefined_opcodes,
+        "external_calls": stats.external_calls,
+        "dictionary_entries": stats.dictionary_entries,
+        "opcodes": stats.opcodes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn count_instructions() {
+        // PUSH1 4 PUSH1 2 ADD JUMP JUMPDEST PUSH2 0x1234 PUSH1 0 SSTORE STOP + metadata
+        let bytecode =
+            hexutil::read_hex("0x6004600201565b61123460005500a165627a7a7230000008").unwrap();
+        let mut program = Program::new(&bytecode);
+        program.optimize();
+        let stats = compute_stats(&program);
+
+        assert_eq!(stats.code_size, 24);
+        assert_eq!(stats.metadata_size, 10);
+        assert_eq!(stats.instructions, 9);
+        assert_eq!(stats.basic_blocks, 2);
+        assert_eq!(stats.jumpdests, 1);
+        assert_eq!((stats.jumps, stats.resolved_jumps), (1, 1));
+        assert_eq!(stats.folded_instructions, 1);
+        assert_eq!(stats.undefined_opcodes, 0);
+        assert_eq!(stats.dictionary_entries, 3);
+        assert_eq!(stats.opcodes["PUSH1"], 3);
+        assert_eq!(stats.opcodes["STOP"], 1);
+
+        let text = stats_text(&stats);
+        assert!(
+            text.contains("jumps:               1 (1 resolved, 100.0%)"),
+            "{}",
+            text
+        );
+        assert!(text.contains("  PUSH1          3\n"), "{}", text);
+        assert_eq!(stats_json(&stats)["opcodes"]["SSTORE"], 1);
+    }
+}
@@ -0,0 +1,365 @@
+// Copyright 2021 Michael Rodler
+// This file is part of evm2cpp.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE. See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <http://www.gnu.org/licenses/>.
+
+use crate::combinedjson::{Combined, Contract, ImmutableReferences, LinkReferences};
+use crate::instructions::Instruction;
+use anyhow::Context;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The `source_map` output of the Vyper compiler. In contrast to solc, the source positions are
+/// given per program counter as `[lineno, col_offset, end_lineno, end_col_offset]`, with 1-based
+/// lines and 0-based columns.
+#[derive(Debug, Default, Deserialize)]
+pub struct VyperSourceMap {
+    #[serde(default)]
+    pub pc_pos_map: BTreeMap<usize, Vec<Option<usize>>>,
+    /// jump type by program counter (`i`, `o` or `-`, as in solc sourcemaps)
+    #[serde(default)]
+    pub pc_jump_map: BTreeMap<usize, String>,
+}
+
+/// A contract in the output of `vyper -f combined_json`.
+#[derive(Debug, Default, Deserialize)]
+struct VyperContract {
+    #[serde(default)]
+    abi: serde_json::Value,
+    #[serde(default)]
+    bytecode: String,
+    #[serde(default)]
+    bytecode_runtime: String,
+    #[serde(default)]
+    source_map: Option<VyperSourceMap>,
+    /// newer Vyper versions distinguish the sourcemaps of the init and runtime code
+    #[serde(default)]
+    source_map_runtime: Option<VyperSourceMap>,
+}
+
+/// Whether the JSON value is the output of `vyper -f combined_json`, which maps the paths of the
+/// contracts to their outputs.
+pub fn is_vyper_combined_json(value: &serde_json::Value) -> bool {
+    value.as_object().is_some_and(|o| {
+        o.values()
+            .any(|c| c.get("bytecode_runtime").is_some() && c.get("source_map").is_some())
+    })
+}
+
+/// Returns the byte offset of a line (1-based) and column (0-based, in bytes) in the source.
+fn byte_offset(line_starts: &[usize], source_len: usize, line: usize, col: usize) -> Option<usize> {
+    let start = *line_starts.get(line.checked_sub(1)?)?;
+    Some((start + col).min(source_len))
+}
+
+/// Converts a Vyper sourcemap of the given bytecode to a solc sourcemap (with one entry per
+/// instruction), where the Vyper source has the given index in the source list.
+pub fn to_solc_source_map(
+    source_map: &VyperSourceMap,
+    bytecode: &[u8],
+    source: &str,
+    file_index: usize,
+) -> anyhow::Result<String> {
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+        .collect();
+
+    let mut entries = vec![];
+    let mut pc = 0;
+    while pc < bytecode.len() {
+        let position = source_map.pc_pos_map.get(&pc).map(|p| {
+            let field = |i: usize| p.get(i).cloned().flatten();
+            (field(0), field(1), field(2), field(3))
+        });
+        let entry = match position {
+            Some((Some(line), Some(col), end_line, end_col)) => {
+                let start = byte_offset(&line_starts, source.len(), line, col).ok_or_else(|| {
+                    anyhow!(
+                        "invalid vyper sourcemap - pc {} refers to line {}, but the source has only {} lines",
+                        pc,
+                        line,
+                        line_starts.len()
+                    )
+                })?;
+                let end = match (end_line, end_col) {
+                    (Some(l), Some(c)) => {
+                        byte_offset(&line_starts, source.len(), l, c).unwrap_or(start)
+                    }
+                    _ => start,
+                };
+                let jump = match source_map.pc_jump_map.get(&pc).map(|j| j.as_str()) {
+                    Some("i") => "i",
+                    Some("o") => "o",
+                    _ => "-",
+                };
+                format!(
+                    "{}:{}:{}:{}",
+                    start,
+                    end.saturating_sub(start),
+                    file_index,
+                    jump
+                )
+            }
+            _ => "0:0:-1:-".to_string(),
+        };
+        entries.push(entry);
+
+        let push_bytes = Instruction::from_u8(bytecode[pc])
+            .and_then(|i| i.push_bytes())
+            .unwrap_or(0);
+        pc += 1 + push_bytes;
+    }
+    Ok(entries.join(";"))
+}
+
+/// Adds a Vyper contract to the combined output. The name of a Vyper contract is the name of its
+/// source file. The sourcemap is converted to the solc format, if the source file can be read
+/// (relative to the directory of the compiler output or the working directory).
+fn add_contract(
+    combined: &mut Combined,
+    base_dir: &Path,
+    source_path: &str,
+    contract: VyperContract,
+) -> anyhow::Result<()> {
+    let name = Path::new(source_path)
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow!("invalid vyper source path {:?}", source_path))?
+        .to_string();
+    let abi = match contract.abi {
+        serde_json::Value::Null => "[]".to_string(),
+        v => serde_json::to_string(&v)?,
+    };
+
+    let source = std::fs::read_to_string(base_dir.join(source_path))
+        .or_else(|_| std::fs::read_to_string(source_path))
+        .ok();
+    let source_map = contract.source_map_runtime.or(contract.source_map);
+    let srcmap_runtime = match (source, source_map) {
+        (Some(source), Some(source_map)) => {
+            let bytecode = hexutil::read_hex(contract.bytecode_runtime.trim()).map_err(|e| {
+                anyhow!(
+                    "failed to convert runtime bytecode of {} from hex (Error: {:?})",
+                    source_path,
+                    e
+                )
+            })?;
+            let file_index = combined.source_list.len();
+            let srcmap = to_solc_source_map(&source_map, &bytecode, &source, file_index)
+                .with_context(|| format!("failed to convert sourcemap of {}", source_path))?;
+            combined.source_list.push(source_path.to_string());
+            combined
+                .source_contents
+                .insert(source_path.to_string(), source);
+            srcmap
+        }
+        (None, Some(_)) => {
+            println!(
+                "[WARNING] failed to read vyper source file {}; no source information available for contract {}",
+                source_path, name
+            );
+            String::new()
+        }
+        _ => String::new(),
+    };
+
+    combined.contracts.insert(
+        format!("{}:{}", source_path, name),
+        Contract {
+            abi,
+            bin: contract.bytecode,
+            bin_runtime: contract.bytecode_runtime,
+            srcmap: String::new(),
+            srcmap_runtime,
+            generated_sources: vec![],
+            generated_sources_runtime: vec![],
+            link_references: LinkReferences::new(),
+            deployed_link_references: LinkReferences::new(),
+            immutable_references: ImmutableReferences::new(),
+        },
+    );
+    Ok(())
+}
+
+/// Parses the plain output of e.g. `vyper -f bytecode_runtime,abi,source_map Foo.vy`, which
+/// prints every output format on a separate line. If both `bytecode` and `bytecode_runtime` are
+/// given, the init code is the one that contains the runtime code.
+fn parse_plain_output(output: &str) -> anyhow::Result<VyperContract> {
+    let mut contract = VyperContract::default();
+    let mut hex_lines: Vec<&str> = vec![];
+    for line in output.lines().map(|l| l.trim()).filter(|l| !l.is_empty()) {
+        if line.starts_with('[') {
+            contract.abi = serde_json::from_str(line).context("failed to parse vyper ABI")?;
+        } else if line.starts_with('{') {
+            contract.source_map =
+                Some(serde_json::from_str(line).context("failed to parse vyper sourcemap")?);
+        } else if line.starts_with("0x") {
+            hex_lines.push(line);
+        } else {
+            bail!("unexpected line in vyper output: {:?}", line);
+        }
+    }
+    match hex_lines.as_slice() {
+        [runtime] => contract.bytecode_runtime = runtime.to_string(),
+        [a, b] if a.contains(&b[2..]) => {
+            contract.bytecode = a.to_string();
+            contract.bytecode_runtime = b.to_string();
+        }
+        [a, b] => {
+            contract.bytecode = b.to_string();
+            contract.bytecode_runtime = a.to_string();
+        }
+        _ => bail!(
+            "expected the runtime bytecode (and optionally the init code) in the vyper output, but found {} bytecodes",
+            hex_lines.len()
+        ),
+    }
+    Ok(contract)
+}
+
+/// Reads the output of the Vyper compiler, either from `vyper -f combined_json` or the plain output
+/// of `vyper -f bytecode_runtime,abi,source_map Foo.vy` (optionally with `bytecode`). For the plain
+/// output, the source file is expected next to it with the same name and a `.vy` extension.
+pub fn read_vyper_output(path: &str) -> anyhow::Result<Combined> {
+    let output = std::fs::read_to_string(path)
+        .with_context(|| format!("Failed to read vyper output from: {}", path))?;
+    let base_dir = Path::new(path).parent().unwrap_or_else(|| Path::new(""));
+    let mut combined = Combined {
+        contracts: BTreeMap::new(),
+        source_list: vec![],
+        version: String::new(),
+        source_contents: BTreeMap::new(),
+    };
+
+    if let Ok(value) = serde_json::from_str::<serde_json::Value>(&output) {
+        if !is_vyper_combined_json(&value) {
+            bail!("{} is not the combined_json output of vyper", path);
+        }
+        let outputs = value.as_object().unwrap();
+        for (key, contract) in outputs.iter() {
+            if key == "version" {
+                combined.version = contract.as_str().unwrap_or_default().to_string();
+                continue;
+            }
+            if contract.get("bytecode_runtime").is_none() {
+                continue;
+            }
+            let contract: VyperContract = serde_json::from_value(contract.clone())
+                .with_context(|| format!("failed to parse vyper output for {}", key))?;
+            add_contract(&mut combined, base_dir, key, contract)?;
+        }
+    } else {
+        let contract = parse_plain_output(&output)
+            .with_context(|| format!("failed to parse vyper output {}", path))?;
+        let file_name = Path::new(path)
+            .with_extension("vy")
+            .file_name()
+            .and_then(|s| s.to_str())
+            .unwrap()
+            .to_string();
+        add_contract(&mut combined, base_dir, &file_name, contract)?;
+    }
+    Ok(combined)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sourcemap::parse_source_map_from_contents;
+
+    const SOURCE: &str =
+        "# @version 0.3.7\n\n@external\ndef foo(x: uint256) -> uint256:\n    return x + 1\n";
+
+    const SOURCE_MAP: &str = r#"{
+  "breakpoints": [],
+  "error_map": {},
+  "pc_breakpoints": [],
+  "pc_jump_map": { "0": "-", "5": "i" },
+  "pc_pos_map": { "2": [5, 11, 5, 16], "5": [4, 0, 5, 16], "6": [5, 4, null, null] },
+  "pc_pos_map_compressed": ""
+}"#;
+
+    fn source_map() -> VyperSourceMap {
+        serde_json::from_str(SOURCE_MAP).unwrap()
+    }
+
+    #[test]
+    fn convert_source_map() {
+        // PUSH1 0 PUSH1 1 ADD JUMP JUMPDEST
+        let bytecode = hexutil::read_hex("0x600060010156005b").unwrap();
+        let srcmap = to_solc_source_map(&source_map(), &bytecode, SOURCE, 0).unwrap();
+        assert_eq!(
+            srcmap,
+            "0:0:-1:-;71:5:0:-;0:0:-1:-;28:48:0:i;64:0:0:-;0:0:-1:-"
+        );
+
+        let sources = vec![("foo.vy".to_string(), SOURCE.to_string())];
+        let sm = parse_source_map_from_contents(&srcmap, &sources, &[]).unwrap();
+        assert_eq!(sm.len(), 6);
+        assert!(sm[0].location.is_none());
+        assert_eq!(*sm[1].source, "x + 1");
+        assert_eq!(
+            sm[1].location.as_ref().unwrap().to_string(),
+            "foo.vy:5:12-5:17"
+        );
+        assert_eq!(sm[3].jump_type, crate::sourcemap::JumpType::Call);
+
+        let mut invalid = source_map();
+        invalid
+            .pc_pos_map
+            .insert(0, vec![Some(42), Some(0), None, None]);
+        assert!(to_solc_source_map(&invalid, &bytecode, SOURCE, 0).is_err());
+    }
+
+    #[test]
+    fn plain_output() {
+        let output = "0x6001\n0x60026001\n[{\"type\": \"function\", \"name\": \"foo\", \"inputs\": [], \"outputs\": []}]\n{\"pc_pos_map\": {}}\n";
+        let contract = parse_plain_output(output).unwrap();
+        assert_eq!(contract.bytecode, "0x60026001");
+        assert_eq!(contract.bytecode_runtime, "0x6001");
+        assert!(contract.abi.is_array());
+        assert!(contract.source_map.is_some());
+
+        assert!(parse_plain_output("0x6001\n0x6002\n0x6003").is_err());
+        assert!(parse_plain_output("foo").is_err());
+    }
+
+    #[test]
+    fn combined_json() {
+        let dir = crate::testutil::TestDir::new("vyper_combined_json");
+        std::fs::write(dir.join("foo.vy"), SOURCE).unwrap();
+        let output = serde_json::json!({
+            "foo.vy": {
+                "abi": [],
+                "bytecode": "0x600060010156005b",
+                "bytecode_runtime": "0x600060010156005b",
+                "source_map": serde_json::from_str::<serde_json::Value>(SOURCE_MAP).unwrap(),
+                "method_identifiers": {}
+            },
+            "version": "0.3.7+commit.6020b8bb"
+        });
+        let path = dir.join("combined.json");
+        std::fs::write(&path, output.to_string()).unwrap();
+
+        let value: serde_json::Value = output;
+        assert!(is_vyper_combined_json(&value));
+        let combined = read_vyper_output(path.to_str().unwrap()).unwrap();
+        assert_eq!(combined.version, "0.3.7+commit.6020b8bb");
+        assert_eq!(combined.source_list, vec!["foo.vy"]);
+        let contract = &combined.contracts["foo.vy:foo"];
+        assert!(contract.srcmap_runtime.starts_with("0:0:-1:-;71:5:0:-"));
+        assert_eq!(contract.abi, "[]");
+    }
+}